@@ -0,0 +1,135 @@
+use bech32::ToBase32;
+use cosmwasm_std::{StdError, StdResult};
+use sha2::Sha256;
+use sha3::{Digest, Keccak256};
+
+use crate::contract::AddressFormat;
+
+/// Checks that `address` is a well-formed account address for a chain using `format`.
+pub fn validate_address(format: &AddressFormat, address: &str) -> StdResult<()> {
+    match format {
+        AddressFormat::Bech32 { prefix } => validate_bech32(address, prefix),
+        AddressFormat::EvmHex => validate_evm(address),
+    }
+}
+
+fn validate_bech32(address: &str, prefix: &str) -> StdResult<()> {
+    let (hrp, _, variant) = bech32::decode(address)
+        .map_err(|err| StdError::generic_err(format!("Invalid bech32 address {}: {}", address, err)))?;
+    if hrp != prefix {
+        return Err(StdError::generic_err(format!(
+            "Address {} must use the {} prefix",
+            address, prefix
+        )));
+    }
+    if variant != bech32::Variant::Bech32 {
+        return Err(StdError::generic_err(format!(
+            "Address {} is not plain bech32",
+            address
+        )));
+    }
+    Ok(())
+}
+
+/// Accepts all-lowercase or all-uppercase hex; mixed case must match its EIP-55 checksum.
+fn validate_evm(address: &str) -> StdResult<()> {
+    let hex = address
+        .strip_prefix("0x")
+        .ok_or_else(|| StdError::generic_err(format!("EVM address {} must start with 0x", address)))?;
+    if hex.len() != 40 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(StdError::generic_err(format!(
+            "EVM address {} must be 20 hex-encoded bytes",
+            address
+        )));
+    }
+
+    let has_lower = hex.chars().any(|c| c.is_ascii_lowercase());
+    let has_upper = hex.chars().any(|c| c.is_ascii_uppercase());
+    if !(has_lower && has_upper) {
+        return Ok(());
+    }
+
+    let hash = Keccak256::digest(hex.to_ascii_lowercase().as_bytes());
+    for (i, c) in hex.chars().enumerate() {
+        let nibble = (hash[i / 2] >> (if i % 2 == 0 { 4 } else { 0 })) & 0x0f;
+        let expected_upper = nibble >= 8;
+        if c.is_ascii_alphabetic() && c.is_ascii_uppercase() != expected_upper {
+            return Err(StdError::generic_err(format!(
+                "EVM address {} has an invalid EIP-55 checksum",
+                address
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Human-readable part of a bech32 address.
+pub fn bech32_prefix(address: &str) -> StdResult<String> {
+    let (hrp, _, _) = bech32::decode(address)
+        .map_err(|err| StdError::generic_err(format!("Invalid bech32 address {}: {}", address, err)))?;
+    Ok(hrp)
+}
+
+/// Address IBC-hooks executes as on behalf of `original_sender`'s transfers
+/// arriving on local `channel`, as derived by the hooks middleware.
+pub fn ibc_hooks_sender(channel: &str, original_sender: &str, prefix: &str) -> StdResult<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(Sha256::digest(b"ibc-wasm-hook-intermediary"));
+    hasher.update(format!("{}/{}", channel, original_sender).as_bytes());
+    bech32::encode(prefix, hasher.finalize().to_base32(), bech32::Variant::Bech32)
+        .map_err(|err| StdError::generic_err(format!("Cannot encode hooks sender: {}", err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bech32(prefix: &str, variant: bech32::Variant) -> String {
+        bech32::encode(prefix, [7u8; 20].to_base32(), variant).unwrap()
+    }
+
+    fn cosmos() -> AddressFormat {
+        AddressFormat::Bech32 { prefix: "cosmos".to_string() }
+    }
+
+    #[test]
+    fn bech32_needs_prefix_checksum_and_variant() {
+        let address = bech32("cosmos", bech32::Variant::Bech32);
+        validate_address(&cosmos(), &address).unwrap();
+        assert_eq!("cosmos", bech32_prefix(&address).unwrap());
+
+        let err = validate_address(&cosmos(), &bech32("osmo", bech32::Variant::Bech32)).unwrap_err();
+        assert!(err.to_string().contains("must use the cosmos prefix"));
+
+        let mut corrupted = address.clone();
+        let last = corrupted.pop().unwrap();
+        corrupted.push(if last == 'q' { 'p' } else { 'q' });
+        let err = validate_address(&cosmos(), &corrupted).unwrap_err();
+        assert!(err.to_string().contains("Invalid bech32 address"));
+
+        let err = validate_address(&cosmos(), &bech32("cosmos", bech32::Variant::Bech32m)).unwrap_err();
+        assert!(err.to_string().contains("is not plain bech32"));
+    }
+
+    #[test]
+    fn evm_checks_mixed_case_against_eip55() {
+        for address in [
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+            "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+            "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb",
+        ] {
+            validate_address(&AddressFormat::EvmHex, address).unwrap();
+            // Single-case addresses carry no checksum and are always accepted.
+            validate_address(&AddressFormat::EvmHex, &format!("0x{}", address[2..].to_ascii_lowercase())).unwrap();
+            validate_address(&AddressFormat::EvmHex, &format!("0x{}", address[2..].to_ascii_uppercase())).unwrap();
+        }
+
+        let err = validate_address(&AddressFormat::EvmHex, "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD").unwrap_err();
+        assert!(err.to_string().contains("invalid EIP-55 checksum"));
+        let err = validate_address(&AddressFormat::EvmHex, "5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed").unwrap_err();
+        assert!(err.to_string().contains("must start with 0x"));
+        let err = validate_address(&AddressFormat::EvmHex, "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeA").unwrap_err();
+        assert!(err.to_string().contains("must be 20 hex-encoded bytes"));
+    }
+}
@@ -1,19 +1,32 @@
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
-use cosmwasm_std::{to_binary, BankMsg, Binary, Coin, Deps, DepsMut, Env, MessageInfo, Response, StdResult};
+use archway_bindings::types::rewards::{RewardsRecordsResponse, WithdrawRewardsResponse};
+use archway_bindings::{ArchwayMsg, ArchwayQuery, PageRequest};
+use cosmwasm_std::{
+    to_json_binary, to_json_string, BankMsg, Binary, Coin, Deps, DepsMut, Env, HexBinary,
+    MessageInfo, Response, StdResult, Storage,
+};
 use cw2::set_contract_version;
 use cw_storage_plus::Item;
 use cosmwasm_std::{
-    Uint128, CosmosMsg, WasmMsg, QueryRequest, StdError, Order,
+    Uint128, CosmosMsg, WasmMsg, StdError, Order, Decimal, Reply, SubMsg, SubMsgResult, Uint256, from_json,
 };
-use cw_storage_plus::{ Map};
+use cw_storage_plus::{Bound, Map};
+use prost::Message;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::error;
+use crate::address::validate_address;
 use crate::error::ContractError;
-use crate::msg::{ExecuteMsg, GetCountResponse, InstantiateMsg, QueryMsg};
-use crate::state::{State, STATE};
+use crate::msg::{
+    ArchwayConfigResponse, BasketDriftResponse, BridgeExecuteMsg, ChainDestination, ComponentDrift, ConfigUpdate, PriceUpdate, WithdrawOptions, WithdrawResponse, ZapOut,
+    IBCLifecycleComplete, IbcCallbackMemo, MsgTransfer, ProtoCoin, SudoMsg, WormholeAsset, WormholeAssetInfo, ExecuteMsg, InstantiateMsg, QueryMsg,
+    RouterExecuteMsg,
+};
+use crate::ibc::{
+    execute_ibc_invest, execute_process_ibc_packet, execute_set_ibc_counterparty, query_ibc_counterparties,
+    reply_ibc_packet,
+};
 
 // version info for migration info
 const CONTRACT_NAME: &str = "crates.io:euclidfi";
@@ -22,6 +35,67 @@ pub const CONFIG: Item<Config> = Item::new("config");
 pub const BASKETS: Map<&str, BasketConfig> = Map::new("baskets");
 pub const PORTFOLIOS: Map<&str, UserPortfolio> = Map::new("portfolios");
 pub const PRICE_FEEDS: Map<&str, Uint128> = Map::new("price_feeds");
+/// Block time (seconds) each price in `PRICE_FEEDS` was last set.
+pub const PRICE_UPDATED: Map<&str, u64> = Map::new("price_updated");
+/// Tokens curators may build baskets from, keyed by symbol.
+pub const TOKEN_REGISTRY: Map<&str, Token> = Map::new("token_registry");
+/// Curator and bond of every basket created by someone other than the admin.
+pub const BASKET_CURATORS: Map<&str, Curation> = Map::new("basket_curators");
+/// Every composition a basket has had, keyed by (basket name, version).
+pub const BASKET_VERSIONS: Map<(&str, u32), BasketVersion> = Map::new("basket_versions");
+/// Last portfolio a `MigrateHolders` crank visited, keyed by deprecated basket.
+pub const MIGRATION_CURSORS: Map<&str, String> = Map::new("migration_cursors");
+/// Stop-loss and take-profit settings, keyed by (owner, basket name).
+pub const EXIT_TRIGGERS: Map<(&str, &str), ExitTriggers> = Map::new("exit_triggers");
+/// Chains basket tokens can live on, keyed by chain id.
+pub const CHAINS: Map<&str, ChainInfo> = Map::new("chains");
+/// IBC transfers sent in this transaction, waiting for their packet sequence in a reply.
+pub const IBC_TRANSFER_QUEUE: Item<Vec<PendingIbcTransfer>> = Item::new("ibc_transfer_queue");
+/// IBC transfers awaiting an ack or timeout, keyed by (source channel, sequence).
+pub const PENDING_IBC_TRANSFERS: Map<(&str, u64), PendingIbcTransfer> = Map::new("pending_ibc_transfers");
+pub const WITHDRAWAL_SEQ: Item<u64> = Item::new("withdrawal_seq");
+/// Withdrawals with outflows that have not settled yet, keyed by withdrawal id.
+pub const PENDING_WITHDRAWALS: Map<u64, PendingWithdrawal> = Map::new("pending_withdrawals");
+/// Bank and bridge outflows sent in this transaction, in the order their replies arrive.
+pub const OUTFLOW_QUEUE: Item<Vec<OutflowLeg>> = Item::new("outflow_queue");
+/// Pooled token quantities held for each basket, keyed by token symbol.
+pub const BASKET_HOLDINGS: Map<&str, HashMap<String, Uint128>> = Map::new("basket_holdings");
+/// Last portfolio the `Compound` crank visited, keyed by basket.
+pub const COMPOUND_CURSORS: Map<&str, String> = Map::new("compound_cursors");
+pub const SWAP_JOB: Item<SwapJob> = Item::new("swap_job");
+/// Reward tokens funded for the reward program and not yet paid out.
+pub const REWARD_POOL: Item<Uint128> = Item::new("reward_pool");
+/// Cumulative harvested Archway rewards per basket share, keyed by (basket, denom).
+pub const HARVEST_INDEX: Map<(&str, &str), Decimal> = Map::new("harvest_index");
+pub const DCA_SEQ: Item<u64> = Item::new("dca_seq");
+pub const DCA_SCHEDULES: Map<u64, DcaSchedule> = Map::new("dca_schedules");
+/// Index of schedules by their next run time, keyed by (next_run, id).
+pub const DCA_QUEUE: Map<(u64, u64), ()> = Map::new("dca_queue");
+/// Schedules run in this transaction, in the order their replies arrive.
+pub const DCA_RUN_QUEUE: Item<Vec<u64>> = Item::new("dca_run_queue");
+/// Operators allowed to act on a user's portfolio, keyed by (owner, operator).
+pub const OPERATORS: Map<(&str, &str), OperatorApproval> = Map::new("operators");
+
+const HARVEST_REPLY_ID: u64 = 1;
+const SWAP_REPLY_ID: u64 = 2;
+const IBC_TRANSFER_REPLY_ID: u64 = 3;
+pub(crate) const IBC_PACKET_REPLY_ID: u64 = 4;
+const OUTFLOW_REPLY_ID: u64 = 5;
+const DCA_RUN_REPLY_ID: u64 = 6;
+
+/// Prices in `PRICE_FEEDS` are quoted as value per whole token, scaled by this factor.
+pub const PRICE_PRECISION: u128 = 1_000_000;
+/// Decimals of the value unit positions and basket TVL are accounted in.
+pub const VALUE_DECIMALS: u32 = 6;
+
+const SECONDS_PER_YEAR: u128 = 365 * 24 * 60 * 60;
+const BPS_DENOMINATOR: u128 = 10_000;
+const DEFAULT_COMPOUND_LIMIT: u32 = 10;
+const MAX_COMPOUND_LIMIT: u32 = 30;
+const DEFAULT_DCA_LIMIT: u32 = 10;
+const MAX_DCA_LIMIT: u32 = 30;
+const DEFAULT_MIGRATION_LIMIT: u32 = 10;
+const MAX_MIGRATION_LIMIT: u32 = 30;
 
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -31,10 +105,45 @@ pub struct Config {
     pub reward_rate: Uint128,
     pub min_lock_period: u64,
     pub compound_frequency: u64,
+    pub keeper_tip_bps: u16,
+    pub keepers: Vec<String>,
+    pub treasury: String,
+    pub harvest_treasury_bps: u16,
+    pub dex_router: Option<String>,
+    pub max_slippage_bps: u16,
+    pub ibc_timeout_seconds: u64,
+    /// Besides the admin, the only address allowed to publish prices.
+    pub oracle: Option<String>,
+    /// Prices older than this many seconds cannot trigger position exits or
+    /// value migrations.
+    pub price_max_age: u64,
+    pub curator_policy: CuratorPolicy,
+    pub archway_owner_address: Option<String>,
+    pub archway_rewards_address: Option<String>,
+    pub archway_flat_fee: Option<Coin>,
     pub total_value_locked: Uint128,
     pub total_users: u64,
 }
 
+/// Guardrails for baskets created by anyone other than the admin.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct CuratorPolicy {
+    /// Bond required to create a basket; without one only the admin can.
+    pub bond: Option<Coin>,
+    /// Share of a curated basket's deposit fee minted to its curator; the rest
+    /// goes to the treasury.
+    pub fee_share_bps: u16,
+    pub max_fee_bps: u16,
+    pub max_tokens: u8,
+    pub min_drift_threshold_bps: u16,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Curation {
+    pub curator: String,
+    pub bond: Coin,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct BasketConfig {
     name: String,
@@ -42,22 +151,125 @@ pub struct BasketConfig {
     min_investment: Uint128,
     total_value_locked: Uint128,
     active: bool,
+    total_shares: Uint128,
+    drift_threshold_bps: u16,
+    rebalance_interval: u64,
+    last_rebalanced: u64,
+    /// Taken from every deposit as shares for the curator and treasury.
+    fee_bps: u16,
+    /// Latest entry in `BASKET_VERSIONS`.
+    version: u32,
+    /// Set when the basket is deprecated; its holders migrate here.
+    successor: Option<String>,
+}
+
+/// A basket composition and who set it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BasketVersion {
+    pub version: u32,
+    pub tokens: Vec<TokenWeight>,
+    pub created_at: u64,
+    pub author: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub enum AddressFormat {
+    /// Bech32 account addresses with the given human-readable prefix.
+    Bech32 { prefix: String },
+    /// 20-byte `0x`-prefixed hex addresses.
+    EvmHex,
+}
+
+/// A chain basket tokens can live on. Tokens on chains without a bridge are
+/// held and paid out locally, optionally forwarded over ICS-20.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ChainInfo {
+    pub id: String,
+    pub display_name: String,
+    pub address_format: AddressFormat,
+    pub bridge: Option<BridgeConfig>,
+    pub enabled: bool,
+}
+
+/// Shape of the execute message a bridge contract expects.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub enum BridgeMsgSchema {
+    /// `{"bridge": {"destination_chain": .., "recipient": ..}}` with the tokens attached.
+    Generic { destination_chain: String },
+    /// Hyperlane warp route `transfer_remote` to a 32-byte recipient on `dest_domain`.
+    HyperlaneWarp { dest_domain: u32 },
+    /// Wormhole token bridge: `deposit_tokens` followed by `initiate_transfer`
+    /// to `recipient_chain` (Wormhole chain id, e.g. 5 for Polygon, 4 for BSC).
+    Wormhole { recipient_chain: u16 },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-pub enum ChainType {
-    Cosmos,
-    Ethereum,
-    Polygon,
-    Binance,
+pub struct BridgeConfig {
+    pub contract: String,
+    pub schema: BridgeMsgSchema,
+    pub fee_denom: String,
+    pub fee_amount: Uint128,
+}
+
+/// How a Cosmos token reaches its origin chain over ICS-20.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct IbcRoute {
+    /// Local channel the token is sent out on.
+    pub channel_id: String,
+    /// Full denom trace of the local token, e.g. `transfer/channel-0/uosmo`.
+    pub denom_trace: String,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct Token {
     address: String,
-    chain: ChainType,
+    /// Id of the token's entry in `CHAINS`.
+    chain: String,
     symbol: String,
     decimals: u8,
+    ibc: Option<IbcRoute>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PendingIbcTransfer {
+    pub withdrawal_id: u64,
+    pub user: String,
+    pub basket_name: String,
+    pub symbol: String,
+    pub amount: Uint128,
+    pub receiver: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct WithdrawalLeg {
+    pub symbol: String,
+    pub amount: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PendingWithdrawal {
+    pub id: u64,
+    pub user: String,
+    pub basket_name: String,
+    pub requested_at: u64,
+    /// Legs whose outflow has not been confirmed or rolled back yet.
+    pub legs: Vec<WithdrawalLeg>,
+}
+
+/// The bank or bridge submessage of a withdrawal leg, matched to its reply.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OutflowLeg {
+    pub withdrawal_id: u64,
+    pub symbol: String,
+}
+
+/// Outflows of one withdrawal, before they are attached to the response.
+pub(crate) struct WithdrawOutflows {
+    pub withdrawal: PendingWithdrawal,
+    pub messages: Vec<SubMsg<ArchwayMsg>>,
+    pub bridge_fees: Vec<Coin>,
+    pub ibc_transfers: Vec<PendingIbcTransfer>,
+    pub outflow_legs: Vec<OutflowLeg>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -72,12 +284,15 @@ pub struct InvestmentPosition {
     basket_name: String,
     initial_investment: Uint128,
     current_value: Uint128,
-    token_amounts: HashMap<String, Uint128>,
+    shares: Uint128,
     entry_price: HashMap<String, Uint128>,
     last_updated: u64,
     pnl: i64,
     performance: i64,
     auto_compound: bool,
+    last_compounded: u64,
+    /// Basket harvest index per denom as of the position's last settlement.
+    harvest_checkpoints: HashMap<String, Decimal>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -89,6 +304,7 @@ pub struct UserPortfolio {
     investment_history: Vec<InvestmentHistory>,
     rewards_earned: Uint128,
     last_claim: u64,
+    harvest_pending: HashMap<String, Uint128>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -98,6 +314,167 @@ pub struct InvestmentHistory {
     amount: Uint128,
     basket_name: String,
     token_prices: HashMap<String, Uint128>,
+    /// Swaps made on the user's behalf as part of this action.
+    swaps: Vec<SwapRecord>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SwapRecord {
+    pub offer: Coin,
+    pub ask_denom: String,
+    pub expected: Uint128,
+    pub received: Uint128,
+    /// Shortfall against the oracle-priced `expected` amount; negative when the
+    /// router returned more.
+    pub slippage_bps: i32,
+}
+
+impl SwapRecord {
+    fn new(leg: &SwapLeg, received: Uint128) -> Self {
+        let slippage_bps = if leg.expected.is_zero() {
+            0
+        } else {
+            let shortfall = leg.expected.u128() as i128 - received.u128() as i128;
+            (shortfall * BPS_DENOMINATOR as i128 / leg.expected.u128() as i128) as i32
+        };
+        SwapRecord {
+            offer: leg.offer.clone(),
+            ask_denom: leg.ask_denom.clone(),
+            expected: leg.expected,
+            received,
+            slippage_bps,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SwapLeg {
+    pub offer_symbol: String,
+    pub offer: Coin,
+    pub ask_symbol: String,
+    pub ask_denom: String,
+    /// Output at oracle prices, before slippage.
+    pub expected: Uint128,
+    pub min_output: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub enum SwapJobKind {
+    Rebalance,
+    /// Sells a withdrawal's components into `output_symbol` and pays the user
+    /// once every leg has settled.
+    ZapOut {
+        output_symbol: String,
+        min_output: Uint128,
+        received: Uint128,
+        swaps: Vec<SwapRecord>,
+    },
+    /// Buys the basket's components with a single input coin; `received`
+    /// accumulates per component and is deposited once every leg has settled.
+    ZapIn {
+        min_value: Uint128,
+        auto_compound: bool,
+        received: HashMap<String, Uint128>,
+        swaps: Vec<SwapRecord>,
+    },
+    /// Buys components with reward tokens for the auto-compounding positions
+    /// in `credits`, which share the minted shares pro rata once every leg settles.
+    Compound {
+        credits: Vec<(String, Uint128)>,
+        received: HashMap<String, Uint128>,
+        swaps: Vec<SwapRecord>,
+    },
+}
+
+/// Swaps run one at a time: each reply measures what the router returned for
+/// `current` before the next leg is dispatched.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SwapJob {
+    pub kind: SwapJobKind,
+    pub user: String,
+    pub basket_name: String,
+    pub current: SwapLeg,
+    pub ask_balance_before: Uint128,
+    pub remaining: Vec<SwapLeg>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub enum ExitThreshold {
+    /// Move of the position's market value against its cost basis, in basis points.
+    Percent { bps: u32 },
+    /// Absolute market value of the position.
+    Value { value: Uint128 },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ExitTriggers {
+    pub stop_loss: Option<ExitThreshold>,
+    pub take_profit: Option<ExitThreshold>,
+    /// Receivers for bridged components, validated when the triggers are set.
+    pub destinations: Vec<ChainDestination>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub enum DcaEnd {
+    /// Stop after this many runs.
+    AfterRuns { runs: u32 },
+    /// Stop once block time reaches `time` (seconds).
+    AtTime { time: u64 },
+    /// Keep running until the escrow can no longer cover a run.
+    WhenExhausted {},
+}
+
+/// A recurring investment funded from escrow. Each run invests `amount_per_run`,
+/// less the keeper's tip, in kind or through a zap-in.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct DcaSchedule {
+    pub id: u64,
+    pub owner: String,
+    pub basket_name: String,
+    /// Funds not yet spent on runs.
+    pub escrow: Coin,
+    pub amount_per_run: Uint128,
+    pub interval: u64,
+    pub next_run: u64,
+    pub runs: u32,
+    pub end: DcaEnd,
+    pub auto_compound: bool,
+}
+
+impl DcaSchedule {
+    fn is_finished(&self, now: u64) -> bool {
+        let ended = match self.end {
+            DcaEnd::AfterRuns { runs } => self.runs >= runs,
+            DcaEnd::AtTime { time } => now >= time,
+            DcaEnd::WhenExhausted {} => false,
+        };
+        ended || self.escrow.amount < self.amount_per_run
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+pub enum OperatorPermission {
+    /// Toggle auto-compounding on the owner's positions.
+    Compound,
+    /// Claim rewards, which are always paid to the owner.
+    Claim,
+    /// Withdraw to the owner's own address on this chain.
+    Withdraw,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OperatorApproval {
+    pub owner: String,
+    pub operator: String,
+    pub permissions: Vec<OperatorPermission>,
+    /// Block time in seconds after which the approval no longer applies.
+    pub expires: Option<u64>,
+}
+
+impl OperatorApproval {
+    fn is_active(&self, now: u64) -> bool {
+        self.expires.is_none_or(|expires| now < expires)
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -106,27 +483,58 @@ pub enum InvestmentAction {
     Withdraw,
     Rebalance,
     Reinvest,
+    TransferOut,
+    TransferIn,
+    StopLoss,
+    TakeProfit,
+    MigrateOut,
+    MigrateIn,
 }
 
 
 #[entry_point]
 pub fn instantiate(
-    deps: DepsMut,
+    deps: DepsMut<ArchwayQuery>,
     _env: Env,
     info: MessageInfo,
     msg: InstantiateMsg,
-) -> StdResult<Response> {
+) -> StdResult<Response<ArchwayMsg>> {
+    if msg.keeper_tip_bps as u128 > BPS_DENOMINATOR {
+        return Err(StdError::generic_err("Keeper tip exceeds 100%"));
+    }
+    if msg.harvest_treasury_bps as u128 > BPS_DENOMINATOR {
+        return Err(StdError::generic_err("Treasury share exceeds 100%"));
+    }
+    if msg.max_slippage_bps as u128 > BPS_DENOMINATOR {
+        return Err(StdError::generic_err("Slippage limit exceeds 100%"));
+    }
+
     let config = Config {
         admin: msg.admin,
         reward_token: msg.reward_token,
         reward_rate: msg.reward_rate,
         min_lock_period: msg.min_lock_period,
         compound_frequency: msg.compound_frequency,
+        keeper_tip_bps: msg.keeper_tip_bps,
+        keepers: vec![],
+        treasury: deps.api.addr_validate(&msg.treasury)?.to_string(),
+        harvest_treasury_bps: msg.harvest_treasury_bps,
+        dex_router: msg.dex_router.map(|router| deps.api.addr_validate(&router)).transpose()?.map(|a| a.to_string()),
+        max_slippage_bps: msg.max_slippage_bps,
+        ibc_timeout_seconds: msg.ibc_timeout_seconds,
+        oracle: msg.oracle.map(|oracle| deps.api.addr_validate(&oracle)).transpose()?.map(|a| a.to_string()),
+        price_max_age: msg.price_max_age,
+        curator_policy: CuratorPolicy::default(),
+        archway_owner_address: None,
+        archway_rewards_address: None,
+        archway_flat_fee: None,
         total_value_locked: Uint128::zero(),
         total_users: 0,
     };
     
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
     CONFIG.save(deps.storage, &config)?;
+    REWARD_POOL.save(deps.storage, &Uint128::zero())?;
 
     Ok(Response::new()
         .add_attribute("method", "instantiate")
@@ -136,73 +544,244 @@ pub fn instantiate(
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn execute(
-    deps: DepsMut,
-    _env: Env,
+    deps: DepsMut<ArchwayQuery>,
+    env: Env,
     info: MessageInfo,
     msg: ExecuteMsg,
-) -> Result<Response, ContractError> {
+) -> Result<Response<ArchwayMsg>, ContractError> {
     match msg {
-        ExecuteMsg::CreateBasket { name, tokens, min_investment } => {
-            execute_create_basket(deps, _env, info, name, tokens, min_investment)
+        ExecuteMsg::CreateBasket { name, tokens, min_investment, drift_threshold_bps, rebalance_interval, fee_bps } => {
+            execute_create_basket(deps, env, info, name, tokens, min_investment, drift_threshold_bps, rebalance_interval, fee_bps)
+        },
+        ExecuteMsg::UpdateBasket { name, tokens, min_investment, active, drift_threshold_bps, rebalance_interval, fee_bps } => {
+            execute_update_basket(deps, env, info, name, tokens, min_investment, active, drift_threshold_bps, rebalance_interval, fee_bps)
+        },
+        ExecuteMsg::DeprecateBasket { basket_name, successor } => {
+            execute_deprecate_basket(deps, info, basket_name, successor)
+        },
+        ExecuteMsg::MigratePosition { basket_name } => {
+            execute_migrate_position(deps, env, info, basket_name)
+        },
+        ExecuteMsg::MigrateHolders { basket_name, limit } => {
+            execute_migrate_holders(deps, env, basket_name, limit)
+        },
+        ExecuteMsg::ReclaimBond { basket_name } => {
+            execute_reclaim_bond(deps, info, basket_name)
+        },
+        ExecuteMsg::RegisterToken { token } => {
+            execute_register_token(deps, info, token)
+        },
+        ExecuteMsg::DeregisterToken { symbol } => {
+            execute_deregister_token(deps, info, symbol)
+        },
+        ExecuteMsg::SetCuratorPolicy { policy } => {
+            execute_set_curator_policy(deps, info, policy)
         },
-        ExecuteMsg::UpdateBasket { name, tokens, min_investment, active } => {
-            execute_update_basket(deps, info, name, tokens, min_investment, active)
+        ExecuteMsg::Invest { basket_name, min_value, auto_compound, in_kind_tolerance_bps, zap_in } => {
+            execute_invest(deps, env, info, basket_name, min_value, auto_compound, in_kind_tolerance_bps, zap_in)
         },
-        ExecuteMsg::Invest { basket_name, amount, auto_compound } => {
-            execute_invest(deps, env, info, basket_name, amount, auto_compound)
+        ExecuteMsg::Withdraw { basket_name, percentage, remote_recipient, destinations, zap_out, owner } => {
+            let options = WithdrawOptions { remote_recipient, destinations, zap_out, owner };
+            execute_withdraw(deps, env, info, basket_name, WithdrawAmount::Percentage(percentage), options)
         },
-        ExecuteMsg::Withdraw { basket_name, percentage } => {
-            execute_withdraw(deps, env, info, basket_name, percentage)
+        ExecuteMsg::WithdrawShares { basket_name, shares, options } => {
+            execute_withdraw(deps, env, info, basket_name, WithdrawAmount::Shares(shares), options)
         },
-        ExecuteMsg::ClaimRewards {} => {
-            execute_claim_rewards(deps, env, info)
+        ExecuteMsg::WithdrawValue { basket_name, value, options } => {
+            execute_withdraw(deps, env, info, basket_name, WithdrawAmount::Value(value), options)
         },
-        ExecuteMsg::SetAutoCompound { basket_name, enabled } => {
-            execute_set_auto_compound(deps, info, basket_name, enabled)
+        ExecuteMsg::WithdrawMax { basket_name, options } => {
+            execute_withdraw(deps, env, info, basket_name, WithdrawAmount::Max, options)
+        },
+        ExecuteMsg::TransferPosition { basket_name, recipient, shares } => {
+            execute_transfer_position(deps, env, info, basket_name, recipient, shares)
+        },
+        ExecuteMsg::ClaimRewards { owner } => {
+            execute_claim_rewards(deps, env, info, owner)
+        },
+        ExecuteMsg::SetAutoCompound { basket_name, enabled, owner } => {
+            execute_set_auto_compound(deps, env, info, basket_name, enabled, owner)
+        },
+        ExecuteMsg::ApproveOperator { operator, permissions, expires } => {
+            execute_approve_operator(deps, env, info, operator, permissions, expires)
+        },
+        ExecuteMsg::RevokeOperator { operator } => {
+            execute_revoke_operator(deps, info, operator)
+        },
+        ExecuteMsg::UpdatePrices { prices } => {
+            execute_update_prices(deps, env, info, prices)
+        },
+        ExecuteMsg::SetExitTriggers { basket_name, stop_loss, take_profit, destinations } => {
+            execute_set_exit_triggers(deps, info, basket_name, stop_loss, take_profit, destinations)
+        },
+        ExecuteMsg::TriggerExit { owner, basket_name } => {
+            execute_trigger_exit(deps, env, info, owner, basket_name)
+        },
+        ExecuteMsg::Compound { basket_name, limit } => {
+            execute_compound(deps, env, info, basket_name, limit)
+        },
+        ExecuteMsg::FundRewards {} => {
+            execute_fund_rewards(deps, info)
+        },
+        ExecuteMsg::CreateDca { basket_name, amount_per_run, interval, end, auto_compound } => {
+            execute_create_dca(deps, env, info, basket_name, amount_per_run, interval, end, auto_compound)
+        },
+        ExecuteMsg::CancelDca { id } => {
+            execute_cancel_dca(deps, info, id)
+        },
+        ExecuteMsg::RunDca { limit } => {
+            execute_run_dca(deps, env, info, limit)
+        },
+        ExecuteMsg::ExecuteDcaRun { id, keeper, min_value } => {
+            execute_dca_run(deps, env, info, id, keeper, min_value)
         },
         ExecuteMsg::Rebalance { basket_name } => {
             execute_rebalance(deps, env, info, basket_name)
         },
-        ExecuteMsg::UpdateConfig { reward_rate, min_lock_period, compound_frequency } => {
-            execute_update_config(deps, info, reward_rate, min_lock_period, compound_frequency)
+        ExecuteMsg::HarvestArchwayRewards { limit } => {
+            execute_harvest_archway_rewards(deps, env, info, limit)
+        },
+        ExecuteMsg::UpdateArchwayMetadata { owner_address, rewards_address } => {
+            execute_update_archway_metadata(deps, info, owner_address, rewards_address)
+        },
+        ExecuteMsg::SetArchwayFlatFee { amount } => {
+            execute_set_archway_flat_fee(deps, env, info, amount)
+        },
+        ExecuteMsg::SetChain { chain } => {
+            execute_set_chain(deps, info, chain)
+        },
+        ExecuteMsg::RemoveChain { id } => {
+            execute_remove_chain(deps, info, id)
+        },
+        ExecuteMsg::ExecuteOutflowLeg { msgs } => {
+            execute_outflow_leg(env, info, msgs)
+        },
+        ExecuteMsg::ProcessIbcPacket { channel_id, packet } => {
+            execute_process_ibc_packet(deps, env, info, channel_id, packet)
+        },
+        ExecuteMsg::SetIbcCounterparty { counterparty, allowed } => {
+            execute_set_ibc_counterparty(deps, info, counterparty, allowed)
+        },
+        ExecuteMsg::IbcInvest { channel_id, sender, basket_name, min_value, auto_compound } => {
+            execute_ibc_invest(deps, env, info, channel_id, sender, basket_name, min_value, auto_compound)
+        },
+        ExecuteMsg::UpdateConfig(update) => {
+            execute_update_config(deps, info, update)
         },
     }
+    .map_err(ContractError::from)
 }
 
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn reply(deps: DepsMut<ArchwayQuery>, env: Env, msg: Reply) -> StdResult<Response<ArchwayMsg>> {
+    match msg.id {
+        HARVEST_REPLY_ID => reply_harvest_archway_rewards(deps, env, msg),
+        SWAP_REPLY_ID => reply_swap(deps, env, msg),
+        IBC_TRANSFER_REPLY_ID => reply_ibc_transfer(deps, env, msg),
+        IBC_PACKET_REPLY_ID => reply_ibc_packet(deps, env, msg),
+        OUTFLOW_REPLY_ID => reply_outflow(deps, env, msg),
+        DCA_RUN_REPLY_ID => reply_dca_run(deps, msg),
+        id => Err(StdError::generic_err(format!("Unknown reply id: {}", id))),
+    }
+}
 
+/// Receives ICS-20 ack/timeout callbacks for transfers this contract sent. Relies
+/// on the chain's IBC hooks middleware, which calls back the packet's sender.
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn sudo(deps: DepsMut<ArchwayQuery>, env: Env, msg: SudoMsg) -> StdResult<Response<ArchwayMsg>> {
+    match msg {
+        SudoMsg::IBCLifecycleComplete(IBCLifecycleComplete::IBCAck { channel, sequence, success, .. }) => {
+            sudo_ibc_transfer_result(deps, env, channel, sequence, success)
+        },
+        SudoMsg::IBCLifecycleComplete(IBCLifecycleComplete::IBCTimeout { channel, sequence }) => {
+            sudo_ibc_transfer_result(deps, env, channel, sequence, false)
+        },
+    }
+}
 
-
-
-
-
-// #[entry_point]
-// pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
-//     match msg {
-//         QueryMsg::GetConfig {} => to_binary(&query_config(deps)?),
-//         QueryMsg::GetBasket { name } => to_binary(&query_basket(deps, name)?),
-//         QueryMsg::GetPortfolio { address } => to_binary(&query_portfolio(deps, address)?),
-//         QueryMsg::GetInvestmentHistory { address, from_timestamp, to_timestamp } => 
-//             to_binary(&query_investment_history(deps, address, from_timestamp, to_timestamp)?),
-//         QueryMsg::GetPerformanceMetrics { address, basket_name } =>
-//             to_binary(&query_performance_metrics(deps, address, basket_name)?),
-//         QueryMsg::GetRewards { address } => to_binary(&query_rewards(deps, address)?),
-//     }
-// }
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn query(deps: Deps<ArchwayQuery>, env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::GetConfig {} => to_json_binary(&query_config(deps)?),
+        QueryMsg::GetBasket { name } => to_json_binary(&query_basket(deps, name)?),
+        QueryMsg::GetPortfolio { address } => to_json_binary(&query_portfolio(deps, address)?),
+        QueryMsg::GetInvestmentHistory { address, from_timestamp, to_timestamp } => 
+            to_json_binary(&query_investment_history(deps, address, from_timestamp, to_timestamp)?),
+        QueryMsg::GetPerformanceMetrics { address, basket_name } =>
+            to_json_binary(&query_performance_metrics(deps, address, basket_name)?),
+        QueryMsg::GetRewards { address } => to_json_binary(&query_rewards(deps, address)?),
+        QueryMsg::GetRewardPool {} => to_json_binary(&REWARD_POOL.load(deps.storage)?),
+        QueryMsg::GetArchwayConfig {} => to_json_binary(&query_archway_config(deps)?),
+        QueryMsg::GetBasketDrift { name } => to_json_binary(&query_basket_drift(deps, name)?),
+        QueryMsg::GetChain { id } => to_json_binary(&query_chain(deps, id)?),
+        QueryMsg::ListChains {} => to_json_binary(&query_chains(deps)?),
+        QueryMsg::ListBasketVersions { basket_name } => {
+            to_json_binary(&query_basket_versions(deps, basket_name)?)
+        },
+        QueryMsg::GetBasketVersion { basket_name, version, at_time } => {
+            to_json_binary(&query_basket_version(deps, basket_name, version, at_time)?)
+        },
+        QueryMsg::ListRegisteredTokens {} => to_json_binary(&query_registered_tokens(deps)?),
+        QueryMsg::GetCuration { basket_name } => {
+            to_json_binary(&BASKET_CURATORS.may_load(deps.storage, &basket_name)?)
+        },
+        QueryMsg::GetExitTriggers { owner, basket_name } => {
+            to_json_binary(&EXIT_TRIGGERS.may_load(deps.storage, (&owner, &basket_name))?)
+        },
+        QueryMsg::GetDcaSchedule { id } => to_json_binary(&DCA_SCHEDULES.load(deps.storage, id)?),
+        QueryMsg::ListDcaSchedules { owner } => to_json_binary(&query_dca_schedules(deps, owner)?),
+        QueryMsg::ListOperators { owner } => to_json_binary(&query_operators(deps, env, owner)?),
+        QueryMsg::ListPendingWithdrawals { address } => {
+            to_json_binary(&query_pending_withdrawals(deps, address)?)
+        },
+        QueryMsg::ListIbcCounterparties {} => to_json_binary(&query_ibc_counterparties(deps)?),
+    }
+}
 
 
 
 // Execute functions implementation...
+#[allow(clippy::too_many_arguments)]
 pub fn execute_create_basket(
-    deps: DepsMut,
-    _env: Env,
+    deps: DepsMut<ArchwayQuery>,
+    env: Env,
     info: MessageInfo,
     name: String,
     tokens: Vec<TokenWeight>,
     min_investment: Uint128,
-) -> Result<Response, ContractError> {
+    drift_threshold_bps: u16,
+    rebalance_interval: u64,
+    fee_bps: u16,
+) -> StdResult<Response<ArchwayMsg>> {
     let config = CONFIG.load(deps.storage)?;
-    if info.sender.to_string() != config.admin {
-        return Err(ContractError::Unauthorized {  })
+    if BASKETS.has(deps.storage, &name) {
+        return Err(StdError::generic_err(format!("Basket {} already exists", name)));
+    }
+    let total_weight: u32 = tokens.iter().map(|t| t.weight as u32).sum();
+    if total_weight != 100 {
+        return Err(StdError::generic_err("Token weights must sum to 100"));
+    }
+    if fee_bps as u128 > BPS_DENOMINATOR {
+        return Err(StdError::generic_err("Basket fee exceeds 100%"));
+    }
+    validate_token_chains(deps.storage, &tokens)?;
+
+    // Anyone but the admin creates a curated basket by posting the bond.
+    if info.sender != config.admin {
+        let policy = &config.curator_policy;
+        let bond = policy.bond.clone().ok_or_else(|| StdError::generic_err("Unauthorized"))?;
+        if info.funds != [bond.clone()] {
+            return Err(StdError::generic_err(format!("Creating a basket requires a bond of {}", bond)));
+        }
+        check_curator_guardrails(deps.storage, policy, &tokens, drift_threshold_bps, fee_bps)?;
+        BASKET_CURATORS.save(
+            deps.storage,
+            &name,
+            &Curation {
+                curator: info.sender.to_string(),
+                bond,
+            },
+        )?;
     }
 
     let basket = BasketConfig {
@@ -211,9 +790,27 @@ pub fn execute_create_basket(
         min_investment,
         total_value_locked: Uint128::zero(),
         active: true,
+        total_shares: Uint128::zero(),
+        drift_threshold_bps,
+        rebalance_interval,
+        last_rebalanced: env.block.time.seconds(),
+        fee_bps,
+        version: 1,
+        successor: None,
     };
 
+    BASKET_VERSIONS.save(
+        deps.storage,
+        (&name, basket.version),
+        &BasketVersion {
+            version: basket.version,
+            tokens: basket.tokens.clone(),
+            created_at: env.block.time.seconds(),
+            author: info.sender.to_string(),
+        },
+    )?;
     BASKETS.save(deps.storage, &name, &basket)?;
+    BASKET_HOLDINGS.save(deps.storage, &name, &HashMap::new())?;
 
     Ok(Response::new()
         .add_attribute("action", "create_basket")
@@ -221,32 +818,83 @@ pub fn execute_create_basket(
 }
 
 
+#[allow(clippy::too_many_arguments)]
 pub fn execute_update_basket(
-    deps: DepsMut,
+    deps: DepsMut<ArchwayQuery>,
+    env: Env,
     info: MessageInfo,
     name: String,
     tokens: Vec<TokenWeight>,
     min_investment: Option<Uint128>,
     active: bool,
-) -> Result<Response, ContractError> {
+    drift_threshold_bps: Option<u16>,
+    rebalance_interval: Option<u64>,
+    fee_bps: Option<u16>,
+) -> StdResult<Response<ArchwayMsg>> {
     let config = CONFIG.load(deps.storage)?;
-    if info.sender.to_string() != config.admin {
-        return Err(ContractError::Unauthorized {});
+    let curation = BASKET_CURATORS.may_load(deps.storage, &name)?;
+    let is_curator = curation.is_some_and(|c| info.sender == c.curator);
+    if info.sender != config.admin && !is_curator {
+        return Err(StdError::generic_err("Unauthorized"));
     }
 
     let mut basket = BASKETS.load(deps.storage, &name)?;
+    // Curators may pause their basket, but only the admin can reopen one.
+    if active && !basket.active && info.sender != config.admin {
+        return Err(StdError::generic_err("Only the admin can reactivate a basket"));
+    }
+    if active && basket.successor.is_some() {
+        return Err(StdError::generic_err("Deprecated baskets cannot be reactivated"));
+    }
 
-    let total_weight: u8 = tokens.iter().map(|t| t.weight).sum();
+    let total_weight: u32 = tokens.iter().map(|t| t.weight as u32).sum();
     if total_weight != 100 {
-        return Err(ContractError::Unauthorized {});
-        
+        return Err(StdError::generic_err("Token weights must sum to 100"));
     }
+    validate_token_chains(deps.storage, &tokens)?;
+    ensure_holdings_covered(deps.storage, &name, &tokens)?;
+    ensure_successor_covers(deps.storage, &name, &tokens)?;
 
+    if tokens != basket.tokens {
+        basket.version += 1;
+        BASKET_VERSIONS.save(
+            deps.storage,
+            (&name, basket.version),
+            &BasketVersion {
+                version: basket.version,
+                tokens: tokens.clone(),
+                created_at: env.block.time.seconds(),
+                author: info.sender.to_string(),
+            },
+        )?;
+    }
     basket.tokens = tokens;
     if let Some(min_inv) = min_investment {
         basket.min_investment = min_inv;
     }
     basket.active = active;
+    if let Some(threshold) = drift_threshold_bps {
+        basket.drift_threshold_bps = threshold;
+    }
+    if let Some(interval) = rebalance_interval {
+        basket.rebalance_interval = interval;
+    }
+    if let Some(fee) = fee_bps {
+        if fee as u128 > BPS_DENOMINATOR {
+            return Err(StdError::generic_err("Basket fee exceeds 100%"));
+        }
+        basket.fee_bps = fee;
+    }
+    // The admin can override the guardrails; curators cannot.
+    if info.sender != config.admin {
+        check_curator_guardrails(
+            deps.storage,
+            &config.curator_policy,
+            &basket.tokens,
+            basket.drift_threshold_bps,
+            basket.fee_bps,
+        )?;
+    }
 
     BASKETS.save(deps.storage, &name, &basket)?;
 
@@ -256,335 +904,3445 @@ pub fn execute_update_basket(
 }
 
 
-
-
-pub mod execute {
-    use super::*;
-
-    pub fn increment(deps: DepsMut) -> Result<Response, ContractError> {
-        STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
-            state.count += 1;
-            Ok(state)
-        })?;
-
-        Ok(Response::new().add_attribute("action", "increment"))
+/// Closes `basket_name` to new deposits and names the basket its holders move
+/// to. Migration hands over holdings in kind, so the successor must hold every
+/// component of the deprecated basket; its own rebalancing restores its weights.
+pub fn execute_deprecate_basket(
+    deps: DepsMut<ArchwayQuery>,
+    info: MessageInfo,
+    basket_name: String,
+    successor: String,
+) -> StdResult<Response<ArchwayMsg>> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(StdError::generic_err("Unauthorized"));
     }
-
-    pub fn reset(deps: DepsMut, info: MessageInfo, count: i32) -> Result<Response, ContractError> {
-        STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
-            if info.sender != state.owner {
-                return Err(ContractError::Unauthorized {});
-            }
-            state.count = count;
-            Ok(state)
-        })?;
-        Ok(Response::new().add_attribute("action", "reset"))
+    if successor == basket_name {
+        return Err(StdError::generic_err("A basket cannot succeed itself"));
+    }
+    let mut basket = BASKETS.load(deps.storage, &basket_name)?;
+    if basket.successor.is_some() {
+        return Err(StdError::generic_err("Basket is already deprecated"));
+    }
+    let target = BASKETS.load(deps.storage, &successor)?;
+    if !target.active || target.successor.is_some() {
+        return Err(StdError::generic_err("Successor basket is not active"));
+    }
+    for token in &basket.tokens {
+        if find_token(&target.tokens, &token.token.symbol)? != &token.token {
+            return Err(StdError::generic_err(format!(
+                "Successor holds a different {} token",
+                token.token.symbol
+            )));
+        }
     }
-}
-
-// #[cfg_attr(not(feature = "library"), entry_point)]
-// pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
-//     match msg {
-//         QueryMsg::GetCount {} => to_binary(&query::count(deps)?),
-//     }
-// }
 
-pub mod query {
-    use super::*;
+    basket.active = false;
+    basket.successor = Some(successor.clone());
+    BASKETS.save(deps.storage, &basket_name, &basket)?;
 
-    pub fn count(deps: Deps) -> StdResult<GetCountResponse> {
-        let state = STATE.load(deps.storage)?;
-        Ok(GetCountResponse { count: state.count })
-    }
+    Ok(Response::new()
+        .add_attribute("action", "deprecate_basket")
+        .add_attribute("basket", basket_name)
+        .add_attribute("successor", successor))
 }
 
-
-pub fn execute_invest(
-    deps: DepsMut,
+pub fn execute_migrate_position(
+    deps: DepsMut<ArchwayQuery>,
     env: Env,
     info: MessageInfo,
     basket_name: String,
-    amount: Uint128,
-    auto_compound: bool,
-) -> StdResult<Response> {
-    let basket = BASKETS.load(deps.storage, &basket_name)?;
-    if !basket.active {
-        return Err(StdError::generic_err("Basket is not active"));
-    }
-    if amount < basket.min_investment {
-        return Err(StdError::generic_err("Investment below minimum"));
-    }
-
-    let mut portfolio = PORTFOLIOS
-        .may_load(deps.storage, info.sender.as_str())?
-        .unwrap_or(UserPortfolio {
-            total_invested: Uint128::zero(),
-            total_current_value: Uint128::zero(),
-            total_pnl: 0,
-            positions: vec![],
-            investment_history: vec![],
-            rewards_earned: Uint128::zero(),
-            last_claim: env.block.time.seconds(),
-        });
-
-    let token_prices = get_token_prices(deps.as_ref())?;
-    let token_amounts = calculate_token_amounts(&basket.tokens, amount, &token_prices)?;
-
-    let position = InvestmentPosition {
-        user: info.sender.to_string(),
-        basket_name: basket_name.clone(),
-        initial_investment: amount,
-        current_value: amount,
-        token_amounts: token_amounts.clone(),
-        entry_price: token_prices.clone(),
-        last_updated: env.block.time.seconds(),
-        pnl: 0,
-        performance: 0,
-        auto_compound,
-    };
+) -> StdResult<Response<ArchwayMsg>> {
+    let owner = info.sender.to_string();
+    let successor = BASKETS
+        .load(deps.storage, &basket_name)?
+        .successor
+        .ok_or_else(|| StdError::generic_err("Basket is not deprecated"))?;
+    let (value, shares) = migrate_position(deps, &env, &owner, &basket_name, &successor)?
+        .ok_or_else(|| StdError::generic_err("Position not found"))?;
 
-    portfolio.positions.push(position);
-    portfolio.total_invested += amount;
-    portfolio.total_current_value += amount;
+    Ok(Response::new()
+        .add_attribute("action", "migrate_position")
+        .add_attribute("owner", owner)
+        .add_attribute("from", basket_name)
+        .add_attribute("to", successor)
+        .add_attribute("value", value.to_string())
+        .add_attribute("shares", shares.to_string()))
+}
 
-    portfolio.investment_history.push(InvestmentHistory {
-        timestamp: env.block.time.seconds(),
-        action: InvestmentAction::Deposit,
-        amount,
-        basket_name: basket_name.clone(),
-        token_prices: token_prices.clone(),
-    });
+/// Migrates the holders of a deprecated basket in batches of `limit`
+/// portfolios, resuming where the previous call stopped. Anyone may call it.
+pub fn execute_migrate_holders(
+    mut deps: DepsMut<ArchwayQuery>,
+    env: Env,
+    basket_name: String,
+    limit: Option<u32>,
+) -> StdResult<Response<ArchwayMsg>> {
+    let successor = BASKETS
+        .load(deps.storage, &basket_name)?
+        .successor
+        .ok_or_else(|| StdError::generic_err("Basket is not deprecated"))?;
+    let limit = limit.unwrap_or(DEFAULT_MIGRATION_LIMIT).min(MAX_MIGRATION_LIMIT) as usize;
 
-    PORTFOLIOS.save(deps.storage, info.sender.as_str(), &portfolio)?;
+    let cursor = MIGRATION_CURSORS.may_load(deps.storage, &basket_name)?;
+    let owners: Vec<String> = PORTFOLIOS
+        .keys(
+            deps.storage,
+            cursor.as_deref().map(Bound::exclusive),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .collect::<StdResult<_>>()?;
 
-    let mut basket = BASKETS.load(deps.storage, &basket_name)?;
-    basket.total_value_locked += amount;
-    BASKETS.save(deps.storage, &basket_name, &basket)?;
+    let mut migrated = 0u32;
+    for owner in &owners {
+        if migrate_position(deps.branch(), &env, owner, &basket_name, &successor)?.is_some() {
+            migrated += 1;
+        }
+    }
 
-    let mut config = CONFIG.load(deps.storage)?;
-    config.total_value_locked += amount;
-    CONFIG.save(deps.storage, &config)?;
+    let done = owners.len() < limit;
+    match owners.last() {
+        Some(last) if !done => MIGRATION_CURSORS.save(deps.storage, &basket_name, last)?,
+        _ => MIGRATION_CURSORS.remove(deps.storage, &basket_name),
+    }
 
     Ok(Response::new()
-        .add_attribute("action", "invest")
+        .add_attribute("action", "migrate_holders")
         .add_attribute("basket", basket_name)
-        .add_attribute("amount", amount.to_string()))
+        .add_attribute("successor", successor)
+        .add_attribute("scanned", owners.len().to_string())
+        .add_attribute("migrated", migrated.to_string())
+        .add_attribute("done", done.to_string()))
 }
 
-pub fn execute_withdraw(
-    deps: DepsMut,
-    env: Env,
-    info: MessageInfo,
-    basket_name: String,
-    percentage: u8,
-) -> StdResult<Response> {
-    if percentage == 0 || percentage > 100 {
-        return Err(StdError::generic_err("Invalid withdrawal percentage"));
+/// Moves `owner`'s whole position from a deprecated basket into its successor:
+/// their slice of the pooled holdings is handed over in kind and successor
+/// shares are minted for its value, with no fee. Cost basis, entry prices and
+/// exit triggers carry over. Returns `None` if `owner` holds no position.
+fn migrate_position(
+    deps: DepsMut<ArchwayQuery>,
+    env: &Env,
+    owner: &str,
+    basket_name: &str,
+    successor: &str,
+) -> StdResult<Option<(Uint128, Uint128)>> {
+    let mut portfolio = PORTFOLIOS.load(deps.storage, owner)?;
+    let idx = match portfolio.positions.iter().position(|p| p.basket_name == basket_name) {
+        Some(idx) => idx,
+        None => return Ok(None),
+    };
+    // The successor may have been paused or deprecated itself since.
+    let mut target = BASKETS.load(deps.storage, successor)?;
+    if !target.active || target.successor.is_some() {
+        return Err(StdError::generic_err("Successor basket is not active"));
     }
+    // Successor shares are minted at oracle value, so prices must be current.
+    let config = CONFIG.load(deps.storage)?;
+    ensure_prices_fresh(deps.storage, &config, &target.tokens, env.block.time.seconds())?;
+    settle_harvest_rewards(deps.as_ref(), &mut portfolio)?;
+    let old = portfolio.positions.remove(idx);
+    let token_prices = get_token_prices(deps.as_ref())?;
 
-    let mut portfolio = PORTFOLIOS.load(deps.storage, info.sender.as_str())?;
-    let position_idx = portfolio.positions
-        .iter()
-        .position(|p| p.basket_name == basket_name)
-        .ok_or_else(|| StdError::generic_err("Position not found"))?;
+    let mut basket = BASKETS.load(deps.storage, basket_name)?;
+    let released = redeem_from_basket(deps.storage, &mut basket, old.shares, old.current_value)?;
+    BASKETS.save(deps.storage, basket_name, &basket)?;
 
-    let position = &mut portfolio.positions[position_idx];
-    let withdraw_amount = position.current_value.multiply_ratio(percentage as u128, 100u128);
+    let mut holdings = BASKET_HOLDINGS.may_load(deps.storage, successor)?.unwrap_or_default();
+    let value = holdings_value(&target.tokens, &released, &token_prices)?;
+    let shares = shares_for_value(&target, holdings_value(&target.tokens, &holdings, &token_prices)?, value)?;
+    for (symbol, amount) in released {
+        *holdings.entry(symbol).or_default() += amount;
+    }
+    BASKET_HOLDINGS.save(deps.storage, successor, &holdings)?;
+    target.total_shares += shares;
+    target.total_value_locked += value;
+    BASKETS.save(deps.storage, successor, &target)?;
 
-    let token_prices = get_token_prices(deps.as_ref())?;
-    let messages = generate_withdraw_messages(position, &token_prices, percentage)?;
+    let now = env.block.time.seconds();
+    match portfolio.positions.iter_mut().find(|p| p.basket_name == successor) {
+        Some(existing) => {
+            existing.shares += shares;
+            existing.current_value += value;
+            existing.initial_investment += old.initial_investment;
+            existing.last_updated = now;
+        }
+        None => portfolio.positions.push(InvestmentPosition {
+            basket_name: successor.to_string(),
+            current_value: value,
+            shares,
+            last_updated: now,
+            harvest_checkpoints: harvest_index(deps.storage, successor)?,
+            ..old.clone()
+        }),
+    }
+    portfolio.total_current_value = portfolio.total_current_value.saturating_sub(old.current_value) + value;
 
-    position.current_value -= withdraw_amount;
-    portfolio.total_current_value -= withdraw_amount;
+    for (action, basket, amount) in [
+        (InvestmentAction::MigrateOut, basket_name, old.current_value),
+        (InvestmentAction::MigrateIn, successor, value),
+    ] {
+        portfolio.investment_history.push(InvestmentHistory {
+            timestamp: now,
+            action,
+            amount,
+            basket_name: basket.to_string(),
+            token_prices: token_prices.clone(),
+            swaps: vec![],
+        });
+    }
+    PORTFOLIOS.save(deps.storage, owner, &portfolio)?;
 
-    if percentage == 100 {
-        portfolio.positions.remove(position_idx);
+    if let Some(triggers) = EXIT_TRIGGERS.may_load(deps.storage, (owner, basket_name))? {
+        EXIT_TRIGGERS.remove(deps.storage, (owner, basket_name));
+        if !EXIT_TRIGGERS.has(deps.storage, (owner, successor)) {
+            EXIT_TRIGGERS.save(deps.storage, (owner, successor), &triggers)?;
+        }
     }
 
-    portfolio.investment_history.push(InvestmentHistory {
-        timestamp: env.block.time.seconds(),
-        action: InvestmentAction::Withdraw,
-        amount: withdraw_amount,
-        basket_name,
-        token_prices,
-    });
+    CONFIG.update(deps.storage, |mut config| -> StdResult<_> {
+        config.total_value_locked = config.total_value_locked.saturating_sub(old.current_value) + value;
+        Ok(config)
+    })?;
 
-    PORTFOLIOS.save(deps.storage, info.sender.as_str(), &portfolio)?;
+    Ok(Some((value, shares)))
+}
+
+fn check_curator_guardrails(
+    storage: &dyn Storage,
+    policy: &CuratorPolicy,
+    tokens: &[TokenWeight],
+    drift_threshold_bps: u16,
+    fee_bps: u16,
+) -> StdResult<()> {
+    if tokens.len() > policy.max_tokens as usize {
+        return Err(StdError::generic_err(format!(
+            "Curated baskets hold at most {} tokens",
+            policy.max_tokens
+        )));
+    }
+    for token in tokens {
+        if TOKEN_REGISTRY.may_load(storage, &token.token.symbol)?.as_ref() != Some(&token.token) {
+            return Err(StdError::generic_err(format!(
+                "{} is not an approved token",
+                token.token.symbol
+            )));
+        }
+    }
+    if drift_threshold_bps < policy.min_drift_threshold_bps {
+        return Err(StdError::generic_err(format!(
+            "Drift threshold must be at least {} bps",
+            policy.min_drift_threshold_bps
+        )));
+    }
+    if fee_bps > policy.max_fee_bps {
+        return Err(StdError::generic_err(format!(
+            "Basket fee must be at most {} bps",
+            policy.max_fee_bps
+        )));
+    }
+    Ok(())
+}
+
+/// Returns the bond once a curated basket is deactivated and every share
+/// outside the treasury's fee position has been withdrawn.
+pub fn execute_reclaim_bond(
+    deps: DepsMut<ArchwayQuery>,
+    info: MessageInfo,
+    basket_name: String,
+) -> StdResult<Response<ArchwayMsg>> {
+    let curation = BASKET_CURATORS.load(deps.storage, &basket_name)?;
+    if info.sender != curation.curator {
+        return Err(StdError::generic_err("Unauthorized"));
+    }
+    let basket = BASKETS.load(deps.storage, &basket_name)?;
+    if basket.active {
+        return Err(StdError::generic_err("Deactivate the basket before reclaiming its bond"));
+    }
+    let treasury = CONFIG.load(deps.storage)?.treasury;
+    let treasury_shares =
+        position_shares(deps.as_ref(), &treasury, &basket_name).unwrap_or_default();
+    if basket.total_shares > treasury_shares {
+        return Err(StdError::generic_err("Basket still has investors"));
+    }
+
+    BASKET_CURATORS.remove(deps.storage, &basket_name);
+
+    Ok(Response::new()
+        .add_message(BankMsg::Send {
+            to_address: curation.curator,
+            amount: vec![curation.bond.clone()],
+        })
+        .add_attribute("action", "reclaim_bond")
+        .add_attribute("basket", basket_name)
+        .add_attribute("bond", curation.bond.to_string()))
+}
+
+pub fn execute_register_token(
+    deps: DepsMut<ArchwayQuery>,
+    info: MessageInfo,
+    token: Token,
+) -> StdResult<Response<ArchwayMsg>> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(StdError::generic_err("Unauthorized"));
+    }
+    if !CHAINS.has(deps.storage, &token.chain) {
+        return Err(StdError::generic_err(format!("Unknown chain {}", token.chain)));
+    }
+    TOKEN_REGISTRY.save(deps.storage, &token.symbol, &token)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "register_token")
+        .add_attribute("symbol", token.symbol))
+}
+
+/// Existing curated baskets keep the token; only new updates are rejected.
+pub fn execute_deregister_token(
+    deps: DepsMut<ArchwayQuery>,
+    info: MessageInfo,
+    symbol: String,
+) -> StdResult<Response<ArchwayMsg>> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(StdError::generic_err("Unauthorized"));
+    }
+    TOKEN_REGISTRY.remove(deps.storage, &symbol);
+
+    Ok(Response::new()
+        .add_attribute("action", "deregister_token")
+        .add_attribute("symbol", symbol))
+}
+
+pub fn execute_set_curator_policy(
+    deps: DepsMut<ArchwayQuery>,
+    info: MessageInfo,
+    policy: CuratorPolicy,
+) -> StdResult<Response<ArchwayMsg>> {
+    let mut config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(StdError::generic_err("Unauthorized"));
+    }
+    if policy.fee_share_bps as u128 > BPS_DENOMINATOR || policy.max_fee_bps as u128 > BPS_DENOMINATOR {
+        return Err(StdError::generic_err("Curator fees cannot exceed 100%"));
+    }
+    config.curator_policy = policy;
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new().add_attribute("action", "set_curator_policy"))
+}
+
+/// Deposits the attached component tokens in kind, or with `zap_in` swaps a
+/// single attached coin into the components. Either way the deposit must be
+/// worth at least `min_value` at oracle prices.
+#[allow(clippy::too_many_arguments)]
+pub fn execute_invest(
+    deps: DepsMut<ArchwayQuery>,
+    env: Env,
+    info: MessageInfo,
+    basket_name: String,
+    min_value: Uint128,
+    auto_compound: bool,
+    in_kind_tolerance_bps: Option<u16>,
+    zap_in: bool,
+) -> StdResult<Response<ArchwayMsg>> {
+    if info.funds.is_empty() {
+        return Err(StdError::generic_err("Attach the tokens to invest"));
+    }
+    if zap_in {
+        if in_kind_tolerance_bps.is_some() {
+            return Err(StdError::generic_err("In-kind tolerance does not apply to zap-in"));
+        }
+        let input = match info.funds.as_slice() {
+            [coin] if !coin.amount.is_zero() => coin.clone(),
+            _ => return Err(StdError::generic_err("Zap-in takes exactly one input coin")),
+        };
+        return zap_into_basket(deps, env, info.sender.to_string(), basket_name, input, min_value, auto_compound);
+    }
+
+    let (shares, value) = invest_in_kind_for(
+        deps,
+        &env,
+        info.sender.as_str(),
+        &basket_name,
+        &info.funds,
+        min_value,
+        in_kind_tolerance_bps,
+        auto_compound,
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "invest")
+        .add_attribute("basket", basket_name)
+        .add_attribute("amount", value.to_string())
+        .add_attribute("shares", shares.to_string()))
+}
+
+/// Adds component tokens straight to the basket's holdings. With a tolerance,
+/// every component must be supplied within that many basis points of its weight.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn invest_in_kind_for(
+    deps: DepsMut<ArchwayQuery>,
+    env: &Env,
+    owner: &str,
+    basket_name: &str,
+    funds: &[Coin],
+    min_value: Uint128,
+    tolerance_bps: Option<u16>,
+    auto_compound: bool,
+) -> StdResult<(Uint128, Uint128)> {
+    let basket = BASKETS.load(deps.storage, basket_name)?;
+    if !basket.active {
+        return Err(StdError::generic_err("Basket is not active"));
+    }
+
+    let mut deposited = HashMap::new();
+    for coin in funds {
+        let token = basket
+            .tokens
+            .iter()
+            .map(|t| &t.token)
+            .find(|t| t.address == coin.denom)
+            .ok_or_else(|| StdError::generic_err(format!("{} is not a basket component", coin.denom)))?;
+        *deposited.entry(token.symbol.clone()).or_insert(Uint128::zero()) += coin.amount;
+    }
+
+    if let Some(tolerance) = tolerance_bps {
+        let token_prices = get_token_prices(deps.as_ref())?;
+        for drift in component_drift(&deposited, &basket.tokens, &token_prices)? {
+            if drift.drift_bps.unsigned_abs() > tolerance as u32 {
+                return Err(StdError::generic_err(format!(
+                    "{} is {} bps away from its basket weight",
+                    drift.symbol, drift.drift_bps
+                )));
+            }
+        }
+    }
+
+    deposit_components(deps, env, owner, basket, deposited, min_value, auto_compound, vec![])
+}
+
+/// Adds component quantities the contract already holds to the basket's pool
+/// and credits the owner with shares for their value at oracle prices.
+#[allow(clippy::too_many_arguments)]
+fn deposit_components(
+    mut deps: DepsMut<ArchwayQuery>,
+    env: &Env,
+    owner: &str,
+    mut basket: BasketConfig,
+    deposited: HashMap<String, Uint128>,
+    min_value: Uint128,
+    auto_compound: bool,
+    swaps: Vec<SwapRecord>,
+) -> StdResult<(Uint128, Uint128)> {
+    let token_prices = get_token_prices(deps.as_ref())?;
+    let mut holdings = BASKET_HOLDINGS.may_load(deps.storage, &basket.name)?.unwrap_or_default();
+    let basket_value = holdings_value(&basket.tokens, &holdings, &token_prices)?;
+    let value = holdings_value(&basket.tokens, &deposited, &token_prices)?;
+
+    if value < basket.min_investment {
+        return Err(StdError::generic_err("Investment below minimum"));
+    }
+    if value < min_value {
+        return Err(StdError::generic_err(format!(
+            "Deposit is worth {}, below the requested {}",
+            value, min_value
+        )));
+    }
+
+    let shares = shares_for_value(&basket, basket_value, value)?;
+    for (symbol, amount) in deposited {
+        *holdings.entry(symbol).or_default() += amount;
+    }
+    BASKET_HOLDINGS.save(deps.storage, &basket.name, &holdings)?;
+    basket.total_shares += shares;
+    basket.total_value_locked += value;
+    BASKETS.save(deps.storage, &basket.name, &basket)?;
+
+    let (shares, value) = take_basket_fee(deps.branch(), env, &basket, shares, value, &token_prices)?;
+    credit_investment(deps, env, owner, &basket.name, value, shares, token_prices, auto_compound, swaps)?;
+    Ok((shares, value))
+}
+
+/// Splits a single input coin across the basket's weights and swaps each part
+/// into its component. Shares are only credited once the last swap reply has
+/// confirmed what was received.
+pub(crate) fn zap_into_basket(
+    mut deps: DepsMut<ArchwayQuery>,
+    env: Env,
+    owner: String,
+    basket_name: String,
+    input: Coin,
+    min_value: Uint128,
+    auto_compound: bool,
+) -> StdResult<Response<ArchwayMsg>> {
+    let basket = BASKETS.load(deps.storage, &basket_name)?;
+    if !basket.active {
+        return Err(StdError::generic_err("Basket is not active"));
+    }
+    let config = CONFIG.load(deps.storage)?;
+    let token_prices = get_token_prices(deps.as_ref())?;
+    let input_token = denom_token(deps.storage, &basket, &input.denom)?;
+    let (kept, legs) = zap_legs(&basket, &input, input_token.as_ref(), &token_prices, config.max_slippage_bps)?;
+
+    let kind = SwapJobKind::ZapIn {
+        min_value,
+        auto_compound,
+        received: kept,
+        swaps: vec![],
+    };
+    let response = Response::new()
+        .add_attribute("action", "invest")
+        .add_attribute("mode", "zap_in")
+        .add_attribute("basket", &basket_name)
+        .add_attribute("input", input.to_string())
+        .add_attribute("swaps", legs.len().to_string());
+
+    match dispatch_swap(deps.branch(), &env, kind.clone(), owner.clone(), basket_name.clone(), legs)? {
+        Some(swap_msg) => Ok(response.add_submessage(swap_msg)),
+        None => {
+            let credited = finish_zap_in(deps, &env, &owner, &basket_name, kind)?;
+            Ok(response.add_attributes(credited.attributes))
+        }
+    }
+}
+
+/// Whether `denom` is one of the basket's components.
+pub(crate) fn is_component(basket: &BasketConfig, denom: &str) -> bool {
+    basket.tokens.iter().any(|t| t.token.address == denom)
+}
+
+/// The component or registered token whose denom is `denom`, if any.
+fn denom_token(storage: &dyn Storage, basket: &BasketConfig, denom: &str) -> StdResult<Option<Token>> {
+    if let Some(weighted) = basket.tokens.iter().find(|t| t.token.address == denom) {
+        return Ok(Some(weighted.token.clone()));
+    }
+    TOKEN_REGISTRY
+        .range(storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_, token)| token))
+        .find(|item| item.as_ref().map_or(true, |token| token.address == denom))
+        .transpose()
+}
+
+/// Splits `input` across the basket's weights. Parts already in a component's
+/// denom are kept as-is; the rest become swap legs into that component, with
+/// an oracle-based floor when `input_token` has a price.
+fn zap_legs(
+    basket: &BasketConfig,
+    input: &Coin,
+    input_token: Option<&Token>,
+    prices: &HashMap<String, Uint128>,
+    max_slippage_bps: u16,
+) -> StdResult<(HashMap<String, Uint128>, Vec<SwapLeg>)> {
+    let input_price = input_token.and_then(|token| {
+        token_price(prices, &token.symbol).ok().map(|price| (price, token.decimals))
+    });
+    let total_weight: u128 = basket.tokens.iter().map(|t| t.weight as u128).sum();
+    if total_weight == 0 {
+        return Err(StdError::generic_err("Basket has no weighted tokens"));
+    }
+
+    let mut kept = HashMap::new();
+    let mut legs = vec![];
+    let mut allocated = Uint128::zero();
+    for (i, weighted) in basket.tokens.iter().enumerate() {
+        let token = &weighted.token;
+        let part = if i + 1 == basket.tokens.len() {
+            input.amount - allocated
+        } else {
+            input.amount.multiply_ratio(weighted.weight as u128, total_weight)
+        };
+        allocated += part;
+        if part.is_zero() {
+            continue;
+        }
+        if token.address == input.denom {
+            kept.insert(token.symbol.clone(), part);
+            continue;
+        }
+        // Without an input price the legs carry no per-swap floor; `min_value`
+        // guards the deposit as a whole.
+        let expected = match input_price {
+            Some((price, decimals)) => token_quantity(
+                token_value(part, price, decimals)?,
+                token_price(prices, &token.symbol)?,
+                token.decimals,
+            )?,
+            None => Uint128::zero(),
+        };
+        legs.push(SwapLeg {
+            offer_symbol: input_token.map_or_else(|| input.denom.clone(), |t| t.symbol.clone()),
+            offer: Coin {
+                denom: input.denom.clone(),
+                amount: part,
+            },
+            ask_symbol: token.symbol.clone(),
+            ask_denom: token.address.clone(),
+            expected,
+            min_output: expected.multiply_ratio(BPS_DENOMINATOR - max_slippage_bps as u128, BPS_DENOMINATOR),
+        });
+    }
+    Ok((kept, legs))
+}
+
+fn finish_zap_in(
+    deps: DepsMut<ArchwayQuery>,
+    env: &Env,
+    owner: &str,
+    basket_name: &str,
+    kind: SwapJobKind,
+) -> StdResult<Response<ArchwayMsg>> {
+    let (min_value, auto_compound, received, swaps) = match kind {
+        SwapJobKind::ZapIn { min_value, auto_compound, received, swaps } => {
+            (min_value, auto_compound, received, swaps)
+        }
+        _ => return Err(StdError::generic_err("Swap job is not a zap-in")),
+    };
+
+    let basket = BASKETS.load(deps.storage, basket_name)?;
+    let (shares, value) =
+        deposit_components(deps, env, owner, basket, received, min_value, auto_compound, swaps)?;
+
+    Ok(Response::new()
+        .add_attribute("amount", value.to_string())
+        .add_attribute("shares", shares.to_string()))
+}
+
+/// Mints the basket's deposit fee out of freshly issued `shares` to the
+/// curator and treasury, returning the shares and value left for the depositor.
+fn take_basket_fee(
+    mut deps: DepsMut<ArchwayQuery>,
+    env: &Env,
+    basket: &BasketConfig,
+    shares: Uint128,
+    value: Uint128,
+    token_prices: &HashMap<String, Uint128>,
+) -> StdResult<(Uint128, Uint128)> {
+    if basket.fee_bps == 0 {
+        return Ok((shares, value));
+    }
+    let config = CONFIG.load(deps.storage)?;
+    let fee_shares = shares.multiply_ratio(basket.fee_bps as u128, BPS_DENOMINATOR);
+    let fee_value = value.multiply_ratio(basket.fee_bps as u128, BPS_DENOMINATOR);
+
+    let (mut treasury_shares, mut treasury_value) = (fee_shares, fee_value);
+    if let Some(curation) = BASKET_CURATORS.may_load(deps.storage, &basket.name)? {
+        let share = config.curator_policy.fee_share_bps as u128;
+        let curator_shares = fee_shares.multiply_ratio(share, BPS_DENOMINATOR);
+        let curator_value = fee_value.multiply_ratio(share, BPS_DENOMINATOR);
+        if !curator_shares.is_zero() {
+            credit_investment(
+                deps.branch(),
+                env,
+                &curation.curator,
+                &basket.name,
+                curator_value,
+                curator_shares,
+                token_prices.clone(),
+                false,
+                vec![],
+            )?;
+        }
+        treasury_shares -= curator_shares;
+        treasury_value -= curator_value;
+    }
+    if !treasury_shares.is_zero() {
+        credit_investment(
+            deps,
+            env,
+            &config.treasury,
+            &basket.name,
+            treasury_value,
+            treasury_shares,
+            token_prices.clone(),
+            false,
+            vec![],
+        )?;
+    }
+
+    Ok((shares - fee_shares, value - fee_value))
+}
+
+fn empty_portfolio(env: &Env) -> UserPortfolio {
+    UserPortfolio {
+        total_invested: Uint128::zero(),
+        total_current_value: Uint128::zero(),
+        total_pnl: 0,
+        positions: vec![],
+        investment_history: vec![],
+        rewards_earned: Uint128::zero(),
+        last_claim: env.block.time.seconds(),
+        harvest_pending: HashMap::new(),
+    }
+}
+
+/// Books a deposit worth `amount` and the `shares` minted for it into the owner's portfolio.
+#[allow(clippy::too_many_arguments)]
+fn credit_investment(
+    deps: DepsMut<ArchwayQuery>,
+    env: &Env,
+    owner: &str,
+    basket_name: &str,
+    amount: Uint128,
+    shares: Uint128,
+    token_prices: HashMap<String, Uint128>,
+    auto_compound: bool,
+    swaps: Vec<SwapRecord>,
+) -> StdResult<()> {
+    let mut portfolio = PORTFOLIOS
+        .may_load(deps.storage, owner)?
+        .unwrap_or_else(|| empty_portfolio(env));
+    settle_harvest_rewards(deps.as_ref(), &mut portfolio)?;
+
+    match portfolio.positions.iter_mut().find(|p| p.basket_name == basket_name) {
+        Some(position) => {
+            position.initial_investment += amount;
+            position.current_value += amount;
+            position.shares += shares;
+            position.last_updated = env.block.time.seconds();
+            position.auto_compound = auto_compound;
+        }
+        None => portfolio.positions.push(InvestmentPosition {
+            user: owner.to_string(),
+            basket_name: basket_name.to_string(),
+            initial_investment: amount,
+            current_value: amount,
+            shares,
+            entry_price: token_prices.clone(),
+            last_updated: env.block.time.seconds(),
+            pnl: 0,
+            performance: 0,
+            auto_compound,
+            last_compounded: env.block.time.seconds(),
+            harvest_checkpoints: harvest_index(deps.storage, basket_name)?,
+        }),
+    }
+    portfolio.total_invested += amount;
+    portfolio.total_current_value += amount;
+
+    portfolio.investment_history.push(InvestmentHistory {
+        timestamp: env.block.time.seconds(),
+        action: InvestmentAction::Deposit,
+        amount,
+        basket_name: basket_name.to_string(),
+        token_prices,
+        swaps,
+    });
+
+    PORTFOLIOS.save(deps.storage, owner, &portfolio)?;
+
+    let mut config = CONFIG.load(deps.storage)?;
+    config.total_value_locked += amount;
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(())
+}
+
+fn shares_for_value(basket: &BasketConfig, basket_value: Uint128, value: Uint128) -> StdResult<Uint128> {
+    if basket.total_shares.is_zero() {
+        return Ok(value);
+    }
+    if basket_value.is_zero() {
+        return Err(StdError::generic_err("Basket holdings have no value"));
+    }
+    Ok(value.multiply_ratio(basket.total_shares, basket_value))
+}
+
+fn holdings_value(
+    tokens: &[TokenWeight],
+    holdings: &HashMap<String, Uint128>,
+    prices: &HashMap<String, Uint128>,
+) -> StdResult<Uint128> {
+    // Components rebalanced out of the basket leave zero entries behind; those
+    // no longer need a token entry. `ensure_holdings_covered` keeps anything
+    // non-zero in the composition.
+    holdings
+        .iter()
+        .filter(|(_, amount)| !amount.is_zero())
+        .try_fold(Uint128::zero(), |total, (symbol, amount)| -> StdResult<Uint128> {
+            let decimals = find_token(tokens, symbol)
+                .map_err(|_| {
+                    StdError::generic_err(format!("Basket holds {} outside its composition", symbol))
+                })?
+                .decimals;
+            Ok(total + token_value(*amount, token_price(prices, symbol)?, decimals)?)
+        })
+}
+
+/// How much of a position a withdrawal redeems.
+pub enum WithdrawAmount {
+    Percentage(u8),
+    Shares(Uint128),
+    /// Enough shares to release at least this much value.
+    Value(Uint128),
+    Max,
+}
+
+pub fn execute_withdraw(
+    mut deps: DepsMut<ArchwayQuery>,
+    env: Env,
+    info: MessageInfo,
+    basket_name: String,
+    amount: WithdrawAmount,
+    options: WithdrawOptions,
+) -> StdResult<Response<ArchwayMsg>> {
+    let WithdrawOptions { remote_recipient, destinations, zap_out, owner } = options;
+    let owner = resolve_owner(deps.as_ref(), &env, &info, owner, OperatorPermission::Withdraw)?;
+    // Operators may only send funds to the owner's own local address.
+    if owner != info.sender && (remote_recipient.is_some() || !destinations.is_empty()) {
+        return Err(StdError::generic_err("Operators can only withdraw to the owner"));
+    }
+    let shares = shares_to_withdraw(deps.as_ref(), &owner, &basket_name, amount)?;
+
+    if let Some(zap_out) = zap_out {
+        if remote_recipient.is_some() || !destinations.is_empty() {
+            return Err(StdError::generic_err("Zap-out withdrawals are paid out locally"));
+        }
+        return zap_out_position(deps, env, owner, basket_name, shares, zap_out);
+    }
+
+    let basket = BASKETS.load(deps.storage, &basket_name)?;
+    let destinations = resolve_destinations(deps.storage, destinations)?;
+    let payouts = preview_payouts(deps.storage, &basket, shares)?;
+    check_payout_routes(deps.storage, &basket, &payouts, &destinations, remote_recipient.as_deref())?;
+    let market_value = shares_market_value(deps.as_ref(), &basket, shares)?;
+
+    let redemption =
+        redeem_position(deps.branch(), &env, &owner, &basket_name, shares, InvestmentAction::Withdraw)?;
+
+    let outflows = generate_withdraw_messages(
+        deps.storage,
+        &env,
+        &redemption.basket,
+        &owner,
+        remote_recipient.as_deref(),
+        &destinations,
+        &redemption.payouts,
+    )?;
+    ensure_funds_match(&info.funds, &outflows.bridge_fees)?;
+    track_outflows(deps.storage, &outflows)?;
+
+    Ok(Response::new()
+        .set_data(withdraw_data(shares, market_value, &redemption)?)
+        .add_submessages(outflows.messages)
+        .add_attribute("action", "withdraw")
+        .add_attribute("withdrawal_id", outflows.withdrawal.id.to_string())
+        .add_attribute("amount", redemption.value.to_string())
+        .add_attribute("shares", shares.to_string()))
+}
+
+pub(crate) fn position_shares(
+    deps: Deps<ArchwayQuery>,
+    owner: &str,
+    basket_name: &str,
+) -> StdResult<Uint128> {
+    PORTFOLIOS
+        .load(deps.storage, owner)?
+        .positions
+        .iter()
+        .find(|p| p.basket_name == basket_name)
+        .map(|p| p.shares)
+        .ok_or_else(|| StdError::generic_err("Position not found"))
+}
+
+/// Result of burning part of a position: the basket after redemption, the value
+/// taken off the position and the token quantities released for payout.
+pub(crate) struct Redemption {
+    pub basket: BasketConfig,
+    pub value: Uint128,
+    pub payouts: HashMap<String, Uint128>,
+}
+
+/// Burns `shares` of `owner`'s position and releases their slice of the
+/// basket's holdings, recording it in the history as `action`. Outflow
+/// messages are left to the caller.
+pub(crate) fn redeem_position(
+    deps: DepsMut<ArchwayQuery>,
+    env: &Env,
+    owner: &str,
+    basket_name: &str,
+    shares: Uint128,
+    action: InvestmentAction,
+) -> StdResult<Redemption> {
+    if shares.is_zero() {
+        return Err(StdError::generic_err("Nothing to withdraw"));
+    }
+
+    let mut portfolio = PORTFOLIOS.load(deps.storage, owner)?;
+    settle_harvest_rewards(deps.as_ref(), &mut portfolio)?;
+    let position_idx = portfolio.positions
+        .iter()
+        .position(|p| p.basket_name == basket_name)
+        .ok_or_else(|| StdError::generic_err("Position not found"))?;
+
+    let position = &mut portfolio.positions[position_idx];
+    if shares > position.shares {
+        return Err(StdError::generic_err("Insufficient shares"));
+    }
+    let value = position.current_value.multiply_ratio(shares, position.shares);
+
+    let mut basket = BASKETS.load(deps.storage, basket_name)?;
+    let payouts = redeem_from_basket(deps.storage, &mut basket, shares, value)?;
+    BASKETS.save(deps.storage, basket_name, &basket)?;
+
+    position.current_value -= value;
+    position.shares -= shares;
+    portfolio.total_current_value -= value;
+
+    if position.shares.is_zero() {
+        portfolio.positions.remove(position_idx);
+        EXIT_TRIGGERS.remove(deps.storage, (owner, basket_name));
+    }
+
+    portfolio.investment_history.push(InvestmentHistory {
+        timestamp: env.block.time.seconds(),
+        action,
+        amount: value,
+        basket_name: basket_name.to_string(),
+        token_prices: get_token_prices(deps.as_ref())?,
+        swaps: vec![],
+    });
+
+    PORTFOLIOS.save(deps.storage, owner, &portfolio)?;
+
+    CONFIG.update(deps.storage, |mut config| -> StdResult<_> {
+        config.total_value_locked = config.total_value_locked.saturating_sub(value);
+        Ok(config)
+    })?;
+
+    Ok(Redemption {
+        basket,
+        value,
+        payouts,
+    })
+}
+
+/// Non-zero token amounts `shares` would release from the basket's holdings,
+/// without redeeming them.
+pub(crate) fn preview_payouts(
+    storage: &dyn Storage,
+    basket: &BasketConfig,
+    shares: Uint128,
+) -> StdResult<HashMap<String, Uint128>> {
+    if basket.total_shares.is_zero() {
+        return Err(StdError::generic_err("Basket has no shares outstanding"));
+    }
+    Ok(BASKET_HOLDINGS
+        .may_load(storage, &basket.name)?
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(symbol, amount)| (symbol, amount.multiply_ratio(shares, basket.total_shares)))
+        .filter(|(_, payout)| !payout.is_zero())
+        .collect())
+}
+
+/// Burns `shares` and takes their pro-rata slice of every pooled holding out
+/// of the basket. Returns the token quantities released, keyed by symbol.
+fn redeem_from_basket(
+    storage: &mut dyn Storage,
+    basket: &mut BasketConfig,
+    shares: Uint128,
+    value: Uint128,
+) -> StdResult<HashMap<String, Uint128>> {
+    if basket.total_shares.is_zero() {
+        return Err(StdError::generic_err("Basket has no shares outstanding"));
+    }
+
+    let payouts = preview_payouts(storage, basket, shares)?;
+    let mut holdings = BASKET_HOLDINGS.load(storage, &basket.name)?;
+    for (symbol, payout) in &payouts {
+        let amount = holdings.entry(symbol.clone()).or_default();
+        *amount = amount.checked_sub(*payout)?;
+    }
+    BASKET_HOLDINGS.save(storage, &basket.name, &holdings)?;
+
+    basket.total_shares = basket.total_shares.checked_sub(shares)?;
+    basket.total_value_locked = basket.total_value_locked.saturating_sub(value);
+    Ok(payouts)
+}
+
+
+/// Builds the outflow messages for a withdrawal. Tokens on external chains are
+/// routed through the registered bridge; the bridge fees they need are returned
+/// so the caller can check they were attached to the withdrawal.
+pub(crate) fn generate_withdraw_messages(
+    storage: &dyn Storage,
+    env: &Env,
+    basket: &BasketConfig,
+    recipient: &str,
+    remote_recipient: Option<&str>,
+    destinations: &HashMap<String, String>,
+    payouts: &HashMap<String, Uint128>,
+) -> StdResult<WithdrawOutflows> {
+    let withdrawal_id = WITHDRAWAL_SEQ.may_load(storage)?.unwrap_or_default() + 1;
+    let mut outflows = WithdrawOutflows {
+        withdrawal: PendingWithdrawal {
+            id: withdrawal_id,
+            user: recipient.to_string(),
+            basket_name: basket.name.clone(),
+            requested_at: env.block.time.seconds(),
+            legs: vec![],
+        },
+        messages: vec![],
+        bridge_fees: vec![],
+        ibc_transfers: vec![],
+        outflow_legs: vec![],
+    };
+
+    for (token_symbol, withdraw_amount) in payouts {
+        let token = basket.tokens.iter()
+            .find(|t| t.token.symbol == *token_symbol)
+            .ok_or_else(|| StdError::generic_err("Token not found"))?;
+        let coin = Coin {
+            denom: token.token.address.clone(),
+            amount: *withdraw_amount,
+        };
+        outflows.withdrawal.legs.push(WithdrawalLeg {
+            symbol: token_symbol.clone(),
+            amount: *withdraw_amount,
+        });
+
+        let chain = CHAINS.load(storage, &token.token.chain)?;
+        match chain.bridge {
+            None => match (&token.token.ibc, remote_recipient) {
+                (Some(route), Some(receiver)) => {
+                    let timeout_seconds = CONFIG.load(storage)?.ibc_timeout_seconds;
+                    outflows.messages.push(SubMsg::reply_on_success(
+                        ibc_transfer_msg(env, &route.channel_id, receiver, coin, timeout_seconds)?,
+                        IBC_TRANSFER_REPLY_ID,
+                    ));
+                    outflows.ibc_transfers.push(PendingIbcTransfer {
+                        withdrawal_id,
+                        user: recipient.to_string(),
+                        basket_name: basket.name.clone(),
+                        symbol: token_symbol.clone(),
+                        amount: *withdraw_amount,
+                        receiver: receiver.to_string(),
+                    });
+                },
+                (None, Some(_)) => {
+                    return Err(StdError::generic_err(format!(
+                        "{} cannot be sent to a remote chain",
+                        token_symbol
+                    )));
+                },
+                (_, None) => outflows.push_outflow_leg(
+                    env,
+                    withdrawal_id,
+                    token_symbol,
+                    vec![BankMsg::Send {
+                        to_address: recipient.to_string(),
+                        amount: vec![coin],
+                    }
+                    .into()],
+                )?,
+            },
+            Some(bridge) => {
+                if !chain.enabled {
+                    return Err(StdError::generic_err(format!(
+                        "Withdrawals to {} are disabled",
+                        chain.display_name
+                    )));
+                }
+                let destination = destinations.get(&chain.id).ok_or_else(|| {
+                    StdError::generic_err(format!("No destination given for {}", chain.display_name))
+                })?;
+                if !bridge.fee_amount.is_zero() {
+                    add_coin(&mut outflows.bridge_fees, &bridge.fee_denom, bridge.fee_amount);
+                }
+                outflows.push_outflow_leg(
+                    env,
+                    withdrawal_id,
+                    token_symbol,
+                    bridge_transfer_msgs(&bridge, coin, destination)?,
+                )?;
+            },
+        }
+    }
+
+    Ok(outflows)
+}
+
+/// An ICS-20 transfer whose memo has the IBC hooks middleware report its ack
+/// or timeout back to this contract through `sudo`.
+fn ibc_transfer_msg(
+    env: &Env,
+    channel_id: &str,
+    receiver: &str,
+    coin: Coin,
+    timeout_seconds: u64,
+) -> StdResult<CosmosMsg<ArchwayMsg>> {
+    let memo = IbcCallbackMemo {
+        ibc_callback: env.contract.address.to_string(),
+    };
+    let msg = MsgTransfer {
+        source_port: "transfer".to_string(),
+        source_channel: channel_id.to_string(),
+        token: Some(ProtoCoin {
+            denom: coin.denom,
+            amount: coin.amount.to_string(),
+        }),
+        sender: env.contract.address.to_string(),
+        receiver: receiver.to_string(),
+        timeout_timestamp: env.block.time.plus_seconds(timeout_seconds).nanos(),
+        memo: to_json_string(&memo)?,
+    };
+    Ok(CosmosMsg::Stargate {
+        type_url: "/ibc.applications.transfer.v1.MsgTransfer".to_string(),
+        value: Binary::from(msg.encode_to_vec()),
+    })
+}
+
+impl WithdrawOutflows {
+    /// Queues one leg as a single submessage. A leg of several messages, such
+    /// as a Wormhole deposit and transfer, runs through `ExecuteOutflowLeg` so
+    /// it succeeds or fails as a whole.
+    fn push_outflow_leg(
+        &mut self,
+        env: &Env,
+        withdrawal_id: u64,
+        symbol: &str,
+        mut msgs: Vec<CosmosMsg<ArchwayMsg>>,
+    ) -> StdResult<()> {
+        let msg = if msgs.len() == 1 {
+            msgs.remove(0)
+        } else {
+            WasmMsg::Execute {
+                contract_addr: env.contract.address.to_string(),
+                msg: to_json_binary(&ExecuteMsg::ExecuteOutflowLeg { msgs })?,
+                funds: vec![],
+            }
+            .into()
+        };
+        self.messages.push(SubMsg::reply_always(msg, OUTFLOW_REPLY_ID));
+        self.outflow_legs.push(OutflowLeg {
+            withdrawal_id,
+            symbol: symbol.to_string(),
+        });
+        Ok(())
+    }
+}
+
+/// Records a withdrawal and queues its outflows for their replies.
+pub(crate) fn track_outflows(storage: &mut dyn Storage, outflows: &WithdrawOutflows) -> StdResult<()> {
+    let withdrawal = &outflows.withdrawal;
+    WITHDRAWAL_SEQ.save(storage, &withdrawal.id)?;
+    if !withdrawal.legs.is_empty() {
+        PENDING_WITHDRAWALS.save(storage, withdrawal.id, withdrawal)?;
+    }
+    if !outflows.ibc_transfers.is_empty() {
+        IBC_TRANSFER_QUEUE.save(storage, &outflows.ibc_transfers)?;
+    }
+    if !outflows.outflow_legs.is_empty() {
+        OUTFLOW_QUEUE.save(storage, &outflows.outflow_legs)?;
+    }
+    Ok(())
+}
+
+/// Sends every message of one withdrawal leg. Plain messages revert together,
+/// so the caller's reply sees the leg either fully sent or not sent at all.
+pub fn execute_outflow_leg(
+    env: Env,
+    info: MessageInfo,
+    msgs: Vec<CosmosMsg<ArchwayMsg>>,
+) -> StdResult<Response<ArchwayMsg>> {
+    if info.sender != env.contract.address {
+        return Err(StdError::generic_err("Unauthorized"));
+    }
+    Ok(Response::new()
+        .add_messages(msgs)
+        .add_attribute("action", "execute_outflow_leg"))
+}
+
+/// Removes `symbol`'s leg from a pending withdrawal, dropping the record once
+/// nothing is left in flight. Returns the leg if it was still outstanding.
+fn settle_withdrawal_leg(
+    storage: &mut dyn Storage,
+    withdrawal_id: u64,
+    symbol: &str,
+) -> StdResult<Option<(PendingWithdrawal, WithdrawalLeg)>> {
+    let mut withdrawal = match PENDING_WITHDRAWALS.may_load(storage, withdrawal_id)? {
+        Some(withdrawal) => withdrawal,
+        None => return Ok(None),
+    };
+    let index = match withdrawal.legs.iter().position(|l| l.symbol == symbol) {
+        Some(index) => index,
+        None => return Ok(None),
+    };
+    let leg = withdrawal.legs.remove(index);
+    if withdrawal.legs.is_empty() {
+        PENDING_WITHDRAWALS.remove(storage, withdrawal_id);
+    } else {
+        PENDING_WITHDRAWALS.save(storage, withdrawal_id, &withdrawal)?;
+    }
+    Ok(Some((withdrawal, leg)))
+}
+
+/// A failed leg only reverts itself, so its tokens are still here and go back
+/// into the position.
+fn reply_outflow(
+    deps: DepsMut<ArchwayQuery>,
+    env: Env,
+    msg: Reply,
+) -> StdResult<Response<ArchwayMsg>> {
+    let mut queue = OUTFLOW_QUEUE.load(deps.storage)?;
+    if queue.is_empty() {
+        return Err(StdError::generic_err("No outflow awaiting a reply"));
+    }
+    let leg = queue.remove(0);
+    if queue.is_empty() {
+        OUTFLOW_QUEUE.remove(deps.storage);
+    } else {
+        OUTFLOW_QUEUE.save(deps.storage, &queue)?;
+    }
+
+    let response = Response::new()
+        .add_attribute("withdrawal_id", leg.withdrawal_id.to_string())
+        .add_attribute("symbol", &leg.symbol);
+
+    match msg.result {
+        SubMsgResult::Ok(_) => {
+            settle_withdrawal_leg(deps.storage, leg.withdrawal_id, &leg.symbol)?;
+            Ok(response.add_attribute("action", "outflow_sent"))
+        }
+        SubMsgResult::Err(err) => {
+            let (withdrawal, pending) =
+                match settle_withdrawal_leg(deps.storage, leg.withdrawal_id, &leg.symbol)? {
+                    Some(settled) => settled,
+                    None => return Ok(response.add_attribute("action", "outflow_failed")),
+                };
+            let (shares, value) = return_to_position(
+                deps,
+                &env,
+                &withdrawal.user,
+                &withdrawal.basket_name,
+                &pending.symbol,
+                pending.amount,
+            )?;
+            Ok(response
+                .add_attribute("action", "outflow_failed")
+                .add_attribute("error", err)
+                .add_attribute("shares", shares.to_string())
+                .add_attribute("value", value.to_string()))
+        }
+    }
+}
+
+fn shares_to_withdraw(
+    deps: Deps<ArchwayQuery>,
+    owner: &str,
+    basket_name: &str,
+    amount: WithdrawAmount,
+) -> StdResult<Uint128> {
+    let portfolio = PORTFOLIOS.load(deps.storage, owner)?;
+    let position = portfolio
+        .positions
+        .iter()
+        .find(|p| p.basket_name == basket_name)
+        .ok_or_else(|| StdError::generic_err("Position not found"))?;
+
+    match amount {
+        WithdrawAmount::Percentage(percentage) => {
+            if percentage == 0 || percentage > 100 {
+                return Err(StdError::generic_err("Invalid withdrawal percentage"));
+            }
+            Ok(position.shares.multiply_ratio(percentage as u128, 100u128))
+        }
+        WithdrawAmount::Shares(shares) => {
+            if shares > position.shares {
+                return Err(StdError::generic_err("Insufficient shares"));
+            }
+            Ok(shares)
+        }
+        WithdrawAmount::Value(value) => {
+            let basket = BASKETS.load(deps.storage, basket_name)?;
+            let basket_value = shares_market_value(deps, &basket, basket.total_shares)?;
+            let worth = basket_value.multiply_ratio(position.shares, basket.total_shares);
+            if value > worth {
+                return Err(StdError::generic_err(format!("Position is only worth {}", worth)));
+            }
+            // Round up so the shares redeemed are worth at least `value`.
+            let mut shares = value.multiply_ratio(basket.total_shares, basket_value);
+            if basket_value.multiply_ratio(shares, basket.total_shares) < value {
+                shares += Uint128::one();
+            }
+            Ok(shares.min(position.shares))
+        }
+        WithdrawAmount::Max => Ok(position.shares),
+    }
+}
+
+/// Market value of `shares` of the basket: their pro-rata part of its holdings
+/// at oracle prices.
+fn shares_market_value(deps: Deps<ArchwayQuery>, basket: &BasketConfig, shares: Uint128) -> StdResult<Uint128> {
+    if basket.total_shares.is_zero() {
+        return Err(StdError::generic_err("Basket has no shares outstanding"));
+    }
+    let holdings = BASKET_HOLDINGS.may_load(deps.storage, &basket.name)?.unwrap_or_default();
+    let prices = get_token_prices(deps)?;
+    Ok(holdings_value(&basket.tokens, &holdings, &prices)?.multiply_ratio(shares, basket.total_shares))
+}
+
+/// Response data for composing contracts: what a withdrawal actually redeemed,
+/// with `value` at market prices.
+fn withdraw_data(shares: Uint128, value: Uint128, redemption: &Redemption) -> StdResult<Binary> {
+    let payouts = redemption
+        .basket
+        .tokens
+        .iter()
+        .filter_map(|t| {
+            redemption.payouts.get(&t.token.symbol).map(|amount| Coin {
+                denom: t.token.address.clone(),
+                amount: *amount,
+            })
+        })
+        .collect();
+    to_json_binary(&WithdrawResponse {
+        shares,
+        value,
+        payouts,
+    })
+}
+
+/// The token a zap-out pays in: a basket component or any registered token,
+/// as long as it is paid out on this chain.
+fn zap_out_token(storage: &dyn Storage, basket: &BasketConfig, symbol: &str) -> StdResult<Token> {
+    let token = match basket.tokens.iter().find(|t| t.token.symbol == symbol) {
+        Some(weighted) => weighted.token.clone(),
+        None => TOKEN_REGISTRY
+            .may_load(storage, symbol)?
+            .ok_or_else(|| StdError::generic_err(format!("{} is not a registered token", symbol)))?,
+    };
+    if CHAINS.load(storage, &token.chain)?.bridge.is_some() {
+        return Err(StdError::generic_err(format!(
+            "{} cannot be paid out locally",
+            token.symbol
+        )));
+    }
+    Ok(token)
+}
+
+/// Redeems `shares` and swaps every released component into the chosen output
+/// token through the DEX router, one leg at a time.
+fn zap_out_position(
+    mut deps: DepsMut<ArchwayQuery>,
+    env: Env,
+    owner: String,
+    basket_name: String,
+    shares: Uint128,
+    zap_out: ZapOut,
+) -> StdResult<Response<ArchwayMsg>> {
+    let basket = BASKETS.load(deps.storage, &basket_name)?;
+    let output = zap_out_token(deps.storage, &basket, &zap_out.output_symbol)?;
+
+    let market_value = shares_market_value(deps.as_ref(), &basket, shares)?;
+    let redemption =
+        redeem_position(deps.branch(), &env, &owner, &basket_name, shares, InvestmentAction::Withdraw)?;
+    let max_slippage_bps = CONFIG.load(deps.storage)?.max_slippage_bps;
+    let prices = get_token_prices(deps.as_ref())?;
+    let output_price = token_price(&prices, &output.symbol)?;
+
+    let mut received = Uint128::zero();
+    let mut legs = vec![];
+    for token in redemption.basket.tokens.iter().map(|t| &t.token) {
+        let amount = match redemption.payouts.get(&token.symbol) {
+            Some(amount) => *amount,
+            None => continue,
+        };
+        if token.symbol == output.symbol {
+            received += amount;
+            continue;
+        }
+        let value = token_value(amount, token_price(&prices, &token.symbol)?, token.decimals)?;
+        let expected = token_quantity(value, output_price, output.decimals)?;
+        legs.push(SwapLeg {
+            offer_symbol: token.symbol.clone(),
+            offer: Coin {
+                denom: token.address.clone(),
+                amount,
+            },
+            ask_symbol: output.symbol.clone(),
+            ask_denom: output.address.clone(),
+            expected,
+            min_output: expected.multiply_ratio(
+                BPS_DENOMINATOR - max_slippage_bps as u128,
+                BPS_DENOMINATOR,
+            ),
+        });
+    }
+
+    let kind = SwapJobKind::ZapOut {
+        output_symbol: output.symbol.clone(),
+        min_output: zap_out.min_output,
+        received,
+        swaps: vec![],
+    };
+    let response = Response::new()
+        .set_data(withdraw_data(shares, market_value, &redemption)?)
+        .add_attribute("action", "withdraw")
+        .add_attribute("mode", "zap_out")
+        .add_attribute("amount", redemption.value.to_string())
+        .add_attribute("shares", shares.to_string())
+        .add_attribute("swaps", legs.len().to_string());
+
+    match dispatch_swap(deps.branch(), &env, kind.clone(), owner.clone(), basket_name.clone(), legs)? {
+        Some(swap_msg) => Ok(response.add_submessage(swap_msg)),
+        None => {
+            let payout = finish_zap_out(deps, &owner, &basket_name, kind)?;
+            Ok(response
+                .add_submessages(payout.messages)
+                .add_attributes(payout.attributes))
+        }
+    }
+}
+
+/// Pays out a zap-out once its last swap has settled and records the swaps on
+/// the withdrawal's history entry. The output may not be a component, so it is
+/// sent as a plain transfer that reverts the whole zap-out if it fails.
+fn finish_zap_out(
+    deps: DepsMut<ArchwayQuery>,
+    owner: &str,
+    basket_name: &str,
+    kind: SwapJobKind,
+) -> StdResult<Response<ArchwayMsg>> {
+    let (output_symbol, min_output, received, swaps) = match kind {
+        SwapJobKind::ZapOut { output_symbol, min_output, received, swaps } => {
+            (output_symbol, min_output, received, swaps)
+        }
+        _ => return Err(StdError::generic_err("Swap job is not a zap-out")),
+    };
+    if received < min_output {
+        return Err(StdError::generic_err(format!(
+            "Zap-out returned {} {}, below minimum {}",
+            received, output_symbol, min_output
+        )));
+    }
+
+    let mut portfolio = PORTFOLIOS.load(deps.storage, owner)?;
+    if let Some(entry) = portfolio
+        .investment_history
+        .iter_mut()
+        .rev()
+        .find(|h| h.action == InvestmentAction::Withdraw && h.basket_name == basket_name)
+    {
+        entry.swaps = swaps;
+    }
+    PORTFOLIOS.save(deps.storage, owner, &portfolio)?;
+
+    let basket = BASKETS.load(deps.storage, basket_name)?;
+    let output = zap_out_token(deps.storage, &basket, &output_symbol)?;
+    let mut response = Response::new().add_attribute("output", format!("{}{}", received, output_symbol));
+    if !received.is_zero() {
+        response = response.add_message(BankMsg::Send {
+            to_address: owner.to_string(),
+            amount: vec![Coin {
+                denom: output.address,
+                amount: received,
+            }],
+        });
+    }
+    Ok(response)
+}
+
+/// Validates `destinations` against the registry and requires one for every
+/// bridged chain `basket` holds tokens on, keyed by chain id.
+fn resolve_destinations(
+    storage: &dyn Storage,
+    destinations: Vec<ChainDestination>,
+) -> StdResult<HashMap<String, String>> {
+    let mut resolved = HashMap::new();
+    for destination in destinations {
+        let chain = CHAINS.may_load(storage, &destination.chain)?.ok_or_else(|| {
+            StdError::generic_err(format!("Unknown chain {}", destination.chain))
+        })?;
+        validate_address(&chain.address_format, &destination.address)?;
+        resolved.insert(chain.id, destination.address);
+    }
+    Ok(resolved)
+}
+
+/// Checks every token in `payouts` has somewhere to go before anything is
+/// redeemed: bridged chains need a destination, and with a `remote_recipient`
+/// every other token needs an IBC route to a chain that accepts that address.
+pub(crate) fn check_payout_routes(
+    storage: &dyn Storage,
+    basket: &BasketConfig,
+    payouts: &HashMap<String, Uint128>,
+    destinations: &HashMap<String, String>,
+    remote_recipient: Option<&str>,
+) -> StdResult<()> {
+    for symbol in payouts.keys() {
+        let token = find_token(&basket.tokens, symbol)?;
+        let chain = CHAINS.load(storage, &token.chain)?;
+        if chain.bridge.is_some() {
+            if !destinations.contains_key(&chain.id) {
+                return Err(StdError::generic_err(format!(
+                    "No destination given for {}",
+                    chain.display_name
+                )));
+            }
+        } else if let Some(recipient) = remote_recipient {
+            if token.ibc.is_none() {
+                return Err(StdError::generic_err(format!(
+                    "{} cannot be sent to a remote chain",
+                    symbol
+                )));
+            }
+            validate_address(&chain.address_format, recipient)?;
+        }
+    }
+    Ok(())
+}
+
+/// Fails unless every token in `payouts` can be sent out over ICS-20.
+pub(crate) fn ensure_ibc_routable(
+    storage: &dyn Storage,
+    basket: &BasketConfig,
+    payouts: &HashMap<String, Uint128>,
+) -> StdResult<()> {
+    for symbol in payouts.keys() {
+        let token = basket.tokens.iter().find(|t| t.token.symbol == *symbol);
+        let routable = match token {
+            Some(t) if t.token.ibc.is_some() => {
+                CHAINS.load(storage, &t.token.chain)?.bridge.is_none()
+            }
+            _ => false,
+        };
+        if !routable {
+            return Err(StdError::generic_err(format!(
+                "{} cannot be sent to a remote chain",
+                symbol
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Local channel `denom` arrives on over ICS-20, which must be a registered
+/// route of a component or registered token.
+pub(crate) fn ics20_channel(storage: &dyn Storage, basket: &BasketConfig, denom: &str) -> StdResult<String> {
+    denom_token(storage, basket, denom)?
+        .and_then(|token| token.ibc)
+        .map(|route| route.channel_id)
+        .ok_or_else(|| StdError::generic_err(format!("{} has no registered ICS-20 route", denom)))
+}
+
+/// Records the packet sequence of an IBC withdrawal so its ack can be matched.
+fn reply_ibc_transfer(
+    deps: DepsMut<ArchwayQuery>,
+    _env: Env,
+    msg: Reply,
+) -> StdResult<Response<ArchwayMsg>> {
+    let result = msg.result.into_result().map_err(StdError::generic_err)?;
+    let packet_attr = |key: &str| {
+        result
+            .events
+            .iter()
+            .filter(|e| e.ty == "send_packet")
+            .flat_map(|e| e.attributes.iter())
+            .find(|a| a.key == key)
+            .map(|a| a.value.clone())
+            .ok_or_else(|| StdError::generic_err(format!("Missing {} in transfer reply", key)))
+    };
+    let channel = packet_attr("packet_src_channel")?;
+    let sequence: u64 = packet_attr("packet_sequence")?
+        .parse()
+        .map_err(|_| StdError::generic_err("Invalid packet sequence"))?;
+
+    let mut queue = IBC_TRANSFER_QUEUE.load(deps.storage)?;
+    if queue.is_empty() {
+        return Err(StdError::generic_err("No IBC transfer awaiting a sequence"));
+    }
+    let transfer = queue.remove(0);
+    if queue.is_empty() {
+        IBC_TRANSFER_QUEUE.remove(deps.storage);
+    } else {
+        IBC_TRANSFER_QUEUE.save(deps.storage, &queue)?;
+    }
+
+    PENDING_IBC_TRANSFERS.save(deps.storage, (&channel, sequence), &transfer)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "ibc_transfer_sent")
+        .add_attribute("channel", channel)
+        .add_attribute("sequence", sequence.to_string()))
+}
+
+fn sudo_ibc_transfer_result(
+    deps: DepsMut<ArchwayQuery>,
+    env: Env,
+    channel: String,
+    sequence: u64,
+    success: bool,
+) -> StdResult<Response<ArchwayMsg>> {
+    let key = (channel.as_str(), sequence);
+    let transfer = match PENDING_IBC_TRANSFERS.may_load(deps.storage, key)? {
+        Some(transfer) => transfer,
+        None => return Ok(Response::new().add_attribute("action", "ibc_transfer_untracked")),
+    };
+    PENDING_IBC_TRANSFERS.remove(deps.storage, key);
+    settle_withdrawal_leg(deps.storage, transfer.withdrawal_id, &transfer.symbol)?;
+
+    if success {
+        return Ok(Response::new()
+            .add_attribute("action", "ibc_transfer_acked")
+            .add_attribute("user", transfer.user));
+    }
+
+    // The transfer module refunded the tokens to us; put them back in the position.
+    let (shares, value) = return_to_position(deps, &env, &transfer.user, &transfer.basket_name, &transfer.symbol, transfer.amount)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "ibc_transfer_failed")
+        .add_attribute("user", transfer.user)
+        .add_attribute("shares", shares.to_string())
+        .add_attribute("value", value.to_string()))
+}
+
+/// Puts tokens from a failed outflow back into the basket and credits the user
+/// with shares for their value at current prices.
+fn return_to_position(
+    deps: DepsMut<ArchwayQuery>,
+    env: &Env,
+    user: &str,
+    basket_name: &str,
+    symbol: &str,
+    amount: Uint128,
+) -> StdResult<(Uint128, Uint128)> {
+    let prices = get_token_prices(deps.as_ref())?;
+    let mut basket = BASKETS.load(deps.storage, basket_name)?;
+    let mut holdings = BASKET_HOLDINGS.may_load(deps.storage, basket_name)?.unwrap_or_default();
+
+    let decimals = find_token(&basket.tokens, symbol)?.decimals;
+    let value = token_value(amount, token_price(&prices, symbol)?, decimals)?;
+    let shares = shares_for_value(&basket, holdings_value(&basket.tokens, &holdings, &prices)?, value)?;
+
+    *holdings.entry(symbol.to_string()).or_default() += amount;
+    BASKET_HOLDINGS.save(deps.storage, basket_name, &holdings)?;
+    basket.total_shares += shares;
+    basket.total_value_locked += value;
+    BASKETS.save(deps.storage, basket_name, &basket)?;
+
+    let mut portfolio = PORTFOLIOS.load(deps.storage, user)?;
+    settle_harvest_rewards(deps.as_ref(), &mut portfolio)?;
+    match portfolio.positions.iter_mut().find(|p| p.basket_name == basket_name) {
+        Some(position) => {
+            position.shares += shares;
+            position.current_value += value;
+            position.last_updated = env.block.time.seconds();
+        }
+        None => portfolio.positions.push(InvestmentPosition {
+            user: user.to_string(),
+            basket_name: basket_name.to_string(),
+            initial_investment: value,
+            current_value: value,
+            shares,
+            entry_price: prices,
+            last_updated: env.block.time.seconds(),
+            pnl: 0,
+            performance: 0,
+            auto_compound: false,
+            last_compounded: env.block.time.seconds(),
+            harvest_checkpoints: harvest_index(deps.storage, basket_name)?,
+        }),
+    }
+    portfolio.total_current_value += value;
+    PORTFOLIOS.save(deps.storage, user, &portfolio)?;
+
+    CONFIG.update(deps.storage, |mut config| -> StdResult<_> {
+        config.total_value_locked += value;
+        Ok(config)
+    })?;
+
+    Ok((shares, value))
+}
+
+/// Holdings and in-flight outflows are keyed by symbol, so every component the
+/// basket still holds must keep its exact token entry. Components are retired
+/// by setting their weight to 0 and rebalancing before removing them.
+fn ensure_holdings_covered(storage: &dyn Storage, basket_name: &str, tokens: &[TokenWeight]) -> StdResult<()> {
+    let current = BASKETS.load(storage, basket_name)?.tokens;
+    let mut held: Vec<String> = BASKET_HOLDINGS
+        .may_load(storage, basket_name)?
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(_, amount)| !amount.is_zero())
+        .map(|(symbol, _)| symbol)
+        .collect();
+    for item in PENDING_WITHDRAWALS.range(storage, None, None, Order::Ascending) {
+        let (_, withdrawal) = item?;
+        if withdrawal.basket_name == basket_name {
+            held.extend(withdrawal.legs.into_iter().map(|leg| leg.symbol));
+        }
+    }
+
+    for symbol in held {
+        let kept = match (find_token(&current, &symbol), find_token(tokens, &symbol)) {
+            (Ok(old), Ok(new)) => old == new,
+            _ => false,
+        };
+        if !kept {
+            return Err(StdError::generic_err(format!(
+                "Basket still holds {}; set its weight to 0 and rebalance before removing or changing it",
+                symbol
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Deprecated baskets pointing at `basket_name` move their holdings into it on
+/// migration, so it must keep every one of their tokens.
+fn ensure_successor_covers(storage: &dyn Storage, basket_name: &str, tokens: &[TokenWeight]) -> StdResult<()> {
+    for item in BASKETS.range(storage, None, None, Order::Ascending) {
+        let (_, predecessor) = item?;
+        if predecessor.successor.as_deref() != Some(basket_name) {
+            continue;
+        }
+        for token in &predecessor.tokens {
+            if find_token(tokens, &token.token.symbol).ok() != Some(&token.token) {
+                return Err(StdError::generic_err(format!(
+                    "Deprecated basket {} migrates into this one and needs its {} token",
+                    predecessor.name, token.token.symbol
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn validate_token_chains(storage: &dyn Storage, tokens: &[TokenWeight]) -> StdResult<()> {
+    for token in tokens {
+        let chain = CHAINS.may_load(storage, &token.token.chain)?.ok_or_else(|| {
+            StdError::generic_err(format!("Unknown chain {}", token.token.chain))
+        })?;
+        if !chain.enabled {
+            return Err(StdError::generic_err(format!(
+                "Chain {} is disabled",
+                chain.display_name
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn bridge_transfer_msgs(
+    bridge: &BridgeConfig,
+    coin: Coin,
+    recipient: &str,
+) -> StdResult<Vec<CosmosMsg<ArchwayMsg>>> {
+    let mut fee = vec![];
+    if !bridge.fee_amount.is_zero() {
+        add_coin(&mut fee, &bridge.fee_denom, bridge.fee_amount);
+    }
+
+    let (mut messages, msg, mut funds) = match &bridge.schema {
+        BridgeMsgSchema::Generic { destination_chain } => (
+            vec![],
+            BridgeExecuteMsg::Bridge {
+                destination_chain: destination_chain.clone(),
+                recipient: recipient.to_string(),
+            },
+            vec![coin],
+        ),
+        BridgeMsgSchema::HyperlaneWarp { dest_domain } => (
+            vec![],
+            BridgeExecuteMsg::TransferRemote {
+                dest_domain: *dest_domain,
+                recipient: padded_recipient(recipient)?,
+                amount: coin.amount,
+            },
+            vec![coin],
+        ),
+        BridgeMsgSchema::Wormhole { recipient_chain } => (
+            // Native tokens have to be deposited with the bridge before transfer.
+            vec![CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: bridge.contract.clone(),
+                msg: to_json_binary(&BridgeExecuteMsg::DepositTokens {})?,
+                funds: vec![coin.clone()],
+            })],
+            BridgeExecuteMsg::InitiateTransfer {
+                asset: WormholeAsset {
+                    info: WormholeAssetInfo::NativeToken { denom: coin.denom },
+                    amount: coin.amount,
+                },
+                recipient_chain: *recipient_chain,
+                recipient: padded_recipient(recipient)?.into(),
+                fee: Uint128::zero(),
+                nonce: 0,
+            },
+            vec![],
+        ),
+    };
+
+    for coin in fee {
+        add_coin(&mut funds, &coin.denom, coin.amount);
+    }
+    funds.sort_by(|a, b| a.denom.cmp(&b.denom));
+
+    messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: bridge.contract.clone(),
+        msg: to_json_binary(&msg)?,
+        funds,
+    }));
+    Ok(messages)
+}
+
+/// Left-pads a hex address to the 32-byte recipient format Hyperlane and Wormhole expect.
+fn padded_recipient(recipient: &str) -> StdResult<HexBinary> {
+    let bytes = HexBinary::from_hex(recipient.trim_start_matches("0x"))?;
+    if bytes.len() > 32 {
+        return Err(StdError::generic_err("Recipient longer than 32 bytes"));
+    }
+    let mut padded = vec![0u8; 32 - bytes.len()];
+    padded.extend_from_slice(&bytes);
+    Ok(padded.into())
+}
+
+fn add_coin(coins: &mut Vec<Coin>, denom: &str, amount: Uint128) {
+    match coins.iter_mut().find(|c| c.denom == denom) {
+        Some(coin) => coin.amount += amount,
+        None => coins.push(Coin {
+            denom: denom.to_string(),
+            amount,
+        }),
+    }
+}
+
+/// Requires `funds` to be exactly the bridge fees a withdrawal needs, so
+/// nothing attached is left stranded in the contract.
+fn ensure_funds_match(funds: &[Coin], required: &[Coin]) -> StdResult<()> {
+    let sent = |denom: &str| -> Uint128 { funds.iter().filter(|c| c.denom == denom).map(|c| c.amount).sum() };
+    for needed in required {
+        if sent(&needed.denom) < needed.amount {
+            return Err(StdError::generic_err(format!(
+                "Insufficient bridge fee: need {}, sent {}{}",
+                needed, sent(&needed.denom), needed.denom
+            )));
+        }
+    }
+    for coin in funds {
+        let needed: Uint128 = required.iter().filter(|c| c.denom == coin.denom).map(|c| c.amount).sum();
+        if sent(&coin.denom) > needed {
+            return Err(StdError::generic_err(format!(
+                "Sent {}{} but only {}{} is needed for bridge fees",
+                sent(&coin.denom), coin.denom, needed, coin.denom
+            )));
+        }
+    }
+    Ok(())
+}
+pub fn execute_claim_rewards(
+    deps: DepsMut<ArchwayQuery>,
+    env: Env,
+    info: MessageInfo,
+    owner: Option<String>,
+) -> StdResult<Response<ArchwayMsg>> {
+    let owner = resolve_owner(deps.as_ref(), &env, &info, owner, OperatorPermission::Claim)?;
+    let config = CONFIG.load(deps.storage)?;
+    let mut portfolio = PORTFOLIOS.load(deps.storage, &owner)?;
+    settle_harvest_rewards(deps.as_ref(), &mut portfolio)?;
+
+    let rewards = calculate_rewards(&portfolio, &config, env.block.time.seconds())?;
+    let harvested: Vec<Coin> = portfolio.harvest_pending
+        .drain()
+        .filter(|(_, amount)| !amount.is_zero())
+        .map(|(denom, amount)| Coin { denom, amount })
+        .collect();
+    if rewards.is_zero() && harvested.is_empty() {
+        return Err(StdError::generic_err("No rewards to claim"));
+    }
+
+    let mut messages: Vec<CosmosMsg<ArchwayMsg>> = vec![];
+    if !rewards.is_zero() {
+        let pool = REWARD_POOL.load(deps.storage)?;
+        if pool < rewards {
+            return Err(StdError::generic_err("Reward pool cannot fund the claim"));
+        }
+        REWARD_POOL.save(deps.storage, &(pool - rewards))?;
+        portfolio.rewards_earned += rewards;
+        portfolio.last_claim = env.block.time.seconds();
+        messages.push(create_reward_transfer_msg(owner.clone(), rewards, &config)?);
+    }
+    if !harvested.is_empty() {
+        messages.push(CosmosMsg::Bank(BankMsg::Send {
+            to_address: owner.clone(),
+            amount: harvested,
+        }));
+    }
+
+    PORTFOLIOS.save(deps.storage, &owner, &portfolio)?;
+
+    Ok(Response::new()
+        .add_messages(messages)
+        .add_attribute("action", "claim_rewards")
+        .add_attribute("amount", rewards.to_string()))
+}
+
+/// Adds the attached reward tokens to the pool that claims and compounding
+/// are paid from.
+pub fn execute_fund_rewards(
+    deps: DepsMut<ArchwayQuery>,
+    info: MessageInfo,
+) -> StdResult<Response<ArchwayMsg>> {
+    let config = CONFIG.load(deps.storage)?;
+    let amount = match info.funds.as_slice() {
+        [coin] if coin.denom == config.reward_token && !coin.amount.is_zero() => coin.amount,
+        _ => return Err(StdError::generic_err(format!("Send only {} to fund rewards", config.reward_token))),
+    };
+    let pool = REWARD_POOL.update(deps.storage, |pool| -> StdResult<_> { Ok(pool + amount) })?;
+
+    Ok(Response::new()
+        .add_attribute("action", "fund_rewards")
+        .add_attribute("amount", amount.to_string())
+        .add_attribute("reward_pool", pool.to_string()))
+}
+
+fn create_reward_transfer_msg(
+    recipient: String,
+    amount: Uint128,
+    config: &Config,
+) -> StdResult<CosmosMsg<ArchwayMsg>> {
+    Ok(CosmosMsg::Bank(BankMsg::Send {
+        to_address: recipient,
+        amount: vec![Coin {
+            denom: config.reward_token.clone(),
+            amount,
+        }],
+    }))
+}
+
+/// Moves `shares` of a position (all of it if unset) to `recipient` without
+/// touching the basket. The moved part keeps its cost basis, auto-compound
+/// state and entry prices, and the recipient takes on the later of the two
+/// reward lock timers so moved value cannot accrue rewards retroactively.
+pub fn execute_transfer_position(
+    deps: DepsMut<ArchwayQuery>,
+    env: Env,
+    info: MessageInfo,
+    basket_name: String,
+    recipient: String,
+    shares: Option<Uint128>,
+) -> StdResult<Response<ArchwayMsg>> {
+    let recipient = deps.api.addr_validate(&recipient)?.to_string();
+    let sender = info.sender.to_string();
+    if recipient == sender {
+        return Err(StdError::generic_err("Cannot transfer a position to yourself"));
+    }
+    let now = env.block.time.seconds();
+
+    // Harvested rewards accrued so far stay with their current owners.
+    let mut from = PORTFOLIOS.load(deps.storage, &sender)?;
+    settle_harvest_rewards(deps.as_ref(), &mut from)?;
+    let mut to = PORTFOLIOS
+        .may_load(deps.storage, &recipient)?
+        .unwrap_or_else(|| empty_portfolio(&env));
+    settle_harvest_rewards(deps.as_ref(), &mut to)?;
+
+    let idx = from
+        .positions
+        .iter()
+        .position(|p| p.basket_name == basket_name)
+        .ok_or_else(|| StdError::generic_err("Position not found"))?;
+    let position = &mut from.positions[idx];
+    let shares = shares.unwrap_or(position.shares);
+    if shares.is_zero() {
+        return Err(StdError::generic_err("Nothing to transfer"));
+    }
+    if shares > position.shares {
+        return Err(StdError::generic_err("Insufficient shares"));
+    }
+
+    let value = position.current_value.multiply_ratio(shares, position.shares);
+    let basis = position.initial_investment.multiply_ratio(shares, position.shares);
+    let moved = InvestmentPosition {
+        user: recipient.clone(),
+        basket_name: basket_name.clone(),
+        initial_investment: basis,
+        current_value: value,
+        shares,
+        entry_price: position.entry_price.clone(),
+        last_updated: position.last_updated,
+        pnl: 0,
+        performance: 0,
+        auto_compound: position.auto_compound,
+        last_compounded: position.last_compounded,
+        harvest_checkpoints: position.harvest_checkpoints.clone(),
+    };
+    position.shares -= shares;
+    position.current_value -= value;
+    position.initial_investment -= basis;
+    position.last_updated = now;
+    if position.shares.is_zero() {
+        from.positions.remove(idx);
+        EXIT_TRIGGERS.remove(deps.storage, (&sender, &basket_name));
+    }
+    from.total_current_value = from.total_current_value.saturating_sub(value);
+    from.total_invested = from.total_invested.saturating_sub(basis);
+
+    match to.positions.iter_mut().find(|p| p.basket_name == basket_name) {
+        Some(existing) => {
+            existing.shares += moved.shares;
+            existing.current_value += moved.current_value;
+            existing.initial_investment += moved.initial_investment;
+            existing.last_updated = existing.last_updated.max(moved.last_updated);
+        }
+        None => to.positions.push(moved),
+    }
+    to.total_current_value += value;
+    to.total_invested += basis;
+    to.last_claim = to.last_claim.max(from.last_claim);
+
+    let token_prices = get_token_prices(deps.as_ref())?;
+    for (portfolio, action) in [
+        (&mut from, InvestmentAction::TransferOut),
+        (&mut to, InvestmentAction::TransferIn),
+    ] {
+        portfolio.investment_history.push(InvestmentHistory {
+            timestamp: now,
+            action,
+            amount: value,
+            basket_name: basket_name.clone(),
+            token_prices: token_prices.clone(),
+            swaps: vec![],
+        });
+    }
+
+    PORTFOLIOS.save(deps.storage, &sender, &from)?;
+    PORTFOLIOS.save(deps.storage, &recipient, &to)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "transfer_position")
+        .add_attribute("basket", basket_name)
+        .add_attribute("from", sender)
+        .add_attribute("to", recipient)
+        .add_attribute("shares", shares.to_string())
+        .add_attribute("value", value.to_string()))
+}
+
+/// The portfolio a call acts on: the sender's own, or `owner`'s when the
+/// sender holds an active approval for `permission`.
+fn resolve_owner(
+    deps: Deps<ArchwayQuery>,
+    env: &Env,
+    info: &MessageInfo,
+    owner: Option<String>,
+    permission: OperatorPermission,
+) -> StdResult<String> {
+    let owner = match owner {
+        Some(owner) if owner != info.sender => owner,
+        _ => return Ok(info.sender.to_string()),
+    };
+    let approved = OPERATORS
+        .may_load(deps.storage, (&owner, info.sender.as_str()))?
+        .is_some_and(|approval| {
+            approval.is_active(env.block.time.seconds()) && approval.permissions.contains(&permission)
+        });
+    if !approved {
+        return Err(StdError::generic_err("Unauthorized"));
+    }
+    Ok(owner)
+}
+
+pub fn execute_approve_operator(
+    deps: DepsMut<ArchwayQuery>,
+    env: Env,
+    info: MessageInfo,
+    operator: String,
+    permissions: Vec<OperatorPermission>,
+    expires: Option<u64>,
+) -> StdResult<Response<ArchwayMsg>> {
+    let operator = deps.api.addr_validate(&operator)?.to_string();
+    if operator == info.sender {
+        return Err(StdError::generic_err("Cannot approve yourself"));
+    }
+    if permissions.is_empty() {
+        return Err(StdError::generic_err("No permissions given"));
+    }
+    if expires.is_some_and(|expires| expires <= env.block.time.seconds()) {
+        return Err(StdError::generic_err("Approval is already expired"));
+    }
+
+    let approval = OperatorApproval {
+        owner: info.sender.to_string(),
+        operator: operator.clone(),
+        permissions,
+        expires,
+    };
+    OPERATORS.save(deps.storage, (info.sender.as_str(), &operator), &approval)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "approve_operator")
+        .add_attribute("owner", info.sender)
+        .add_attribute("operator", operator))
+}
+
+pub fn execute_revoke_operator(
+    deps: DepsMut<ArchwayQuery>,
+    info: MessageInfo,
+    operator: String,
+) -> StdResult<Response<ArchwayMsg>> {
+    if !OPERATORS.has(deps.storage, (info.sender.as_str(), &operator)) {
+        return Err(StdError::generic_err("Operator not approved"));
+    }
+    OPERATORS.remove(deps.storage, (info.sender.as_str(), &operator));
+
+    Ok(Response::new()
+        .add_attribute("action", "revoke_operator")
+        .add_attribute("owner", info.sender)
+        .add_attribute("operator", operator))
+}
+
+pub fn execute_set_auto_compound(
+    deps: DepsMut<ArchwayQuery>,
+    env: Env,
+    info: MessageInfo,
+    basket_name: String,
+    enabled: bool,
+    owner: Option<String>,
+) -> StdResult<Response<ArchwayMsg>> {
+    let owner = resolve_owner(deps.as_ref(), &env, &info, owner, OperatorPermission::Compound)?;
+    let mut portfolio = PORTFOLIOS.load(deps.storage, &owner)?;
+    let position = portfolio.positions
+        .iter_mut()
+        .find(|p| p.basket_name == basket_name)
+        .ok_or_else(|| StdError::generic_err("Position not found"))?;
+
+    // Accrual for compounding starts from the moment the user opts in.
+    if enabled && !position.auto_compound {
+        position.last_compounded = env.block.time.seconds();
+    }
+    position.auto_compound = enabled;
+    PORTFOLIOS.save(deps.storage, &owner, &portfolio)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_auto_compound")
+        .add_attribute("basket", basket_name)
+        .add_attribute("enabled", enabled.to_string()))
+}
+
+
+/// Permissionless crank that reinvests the accrued rewards of auto-compounding
+/// positions. Portfolios are walked in key order, `limit` at a time, resuming
+/// from where the previous call stopped.
+pub fn execute_compound(
+    mut deps: DepsMut<ArchwayQuery>,
+    env: Env,
+    info: MessageInfo,
+    basket_name: String,
+    limit: Option<u32>,
+) -> StdResult<Response<ArchwayMsg>> {
+    let config = CONFIG.load(deps.storage)?;
+    let basket = BASKETS.load(deps.storage, &basket_name)?;
+    if !basket.active {
+        return Err(StdError::generic_err("Basket is not active"));
+    }
+    let now = env.block.time.seconds();
+    let limit = limit.unwrap_or(DEFAULT_COMPOUND_LIMIT).min(MAX_COMPOUND_LIMIT) as usize;
+
+    let cursor = COMPOUND_CURSORS.may_load(deps.storage, &basket_name)?;
+    let users: Vec<String> = PORTFOLIOS
+        .keys(deps.storage, cursor.as_deref().map(Bound::exclusive), None, Order::Ascending)
+        .take(limit)
+        .collect::<StdResult<_>>()?;
+
+    // Wrap around once the end of the portfolio set is reached.
+    match users.last() {
+        Some(last) if users.len() == limit => COMPOUND_CURSORS.save(deps.storage, &basket_name, last)?,
+        _ => COMPOUND_CURSORS.remove(deps.storage, &basket_name),
+    }
+
+    let mut due = vec![];
+    let mut credits = vec![];
+    let mut total_reinvest = Uint128::zero();
+    let mut total_tip = Uint128::zero();
+
+    for user in users {
+        let portfolio = PORTFOLIOS.load(deps.storage, &user)?;
+        let position = match portfolio.positions.iter().find(|p| p.basket_name == basket_name) {
+            Some(position) if position.auto_compound => position,
+            _ => continue,
+        };
+        let elapsed = now.saturating_sub(position.last_compounded);
+        if elapsed < config.compound_frequency {
+            continue;
+        }
+
+        let reward = accrued_reward(position.current_value, &config, elapsed);
+        let tip = reward.multiply_ratio(config.keeper_tip_bps as u128, BPS_DENOMINATOR);
+        let reinvest = reward - tip;
+        if !reinvest.is_zero() {
+            credits.push((user.clone(), reinvest));
+            total_reinvest += reinvest;
+        }
+        total_tip += tip;
+        due.push(user);
+    }
+
+    let mut response = Response::new()
+        .add_attribute("action", "compound")
+        .add_attribute("basket", &basket_name)
+        .add_attribute("positions", credits.len().to_string())
+        .add_attribute("reinvested", total_reinvest.to_string())
+        .add_attribute("keeper_tip", total_tip.to_string());
+    if total_reinvest.is_zero() && total_tip.is_zero() {
+        return Ok(response);
+    }
+
+    // Rewards are paid out of the funded reward pool, never out of basket
+    // holdings or escrow that happen to share the reward token's denom.
+    let pool = REWARD_POOL.load(deps.storage)?;
+    if pool < total_reinvest + total_tip {
+        return Err(StdError::generic_err("Reward pool cannot fund compounding"));
+    }
+    REWARD_POOL.save(deps.storage, &(pool - total_reinvest - total_tip))?;
+    let token_prices = get_token_prices(deps.as_ref())?;
+    let reward_token = denom_token(deps.storage, &basket, &config.reward_token)?
+        .filter(|token| token_price(&token_prices, &token.symbol).is_ok());
+    if reward_token.is_none() && !total_reinvest.is_zero() {
+        return Err(StdError::generic_err("Reward token has no oracle price"));
+    }
+
+    for user in &due {
+        let mut portfolio = PORTFOLIOS.load(deps.storage, user)?;
+        if let Some(position) = portfolio.positions.iter_mut().find(|p| p.basket_name == basket_name) {
+            position.last_compounded = now;
+        }
+        PORTFOLIOS.save(deps.storage, user, &portfolio)?;
+    }
+
+    if !total_tip.is_zero() {
+        response = response.add_message(BankMsg::Send {
+            to_address: info.sender.to_string(),
+            amount: vec![Coin {
+                denom: config.reward_token.clone(),
+                amount: total_tip,
+            }],
+        });
+    }
+    if total_reinvest.is_zero() {
+        return Ok(response);
+    }
+
+    // Swap floors come from the oracle, so a keeper cannot route the rewards
+    // through a manipulated pool.
+    let input = Coin {
+        denom: config.reward_token.clone(),
+        amount: total_reinvest,
+    };
+    let (kept, legs) = zap_legs(&basket, &input, reward_token.as_ref(), &token_prices, config.max_slippage_bps)?;
+
+    let kind = SwapJobKind::Compound {
+        credits,
+        received: kept,
+        swaps: vec![],
+    };
+    match dispatch_swap(deps.branch(), &env, kind.clone(), info.sender.to_string(), basket_name.clone(), legs)? {
+        Some(swap_msg) => Ok(response.add_submessage(swap_msg)),
+        None => {
+            let credited = finish_compound(deps, &env, &basket_name, kind)?;
+            Ok(response.add_attributes(credited.attributes))
+        }
+    }
+}
+
+/// Adds the components bought with compounded rewards to the basket and
+/// splits the shares minted for them across the credited positions.
+fn finish_compound(
+    deps: DepsMut<ArchwayQuery>,
+    env: &Env,
+    basket_name: &str,
+    kind: SwapJobKind,
+) -> StdResult<Response<ArchwayMsg>> {
+    let (credits, received, swaps) = match kind {
+        SwapJobKind::Compound { credits, received, swaps } => (credits, received, swaps),
+        _ => return Err(StdError::generic_err("Swap job is not a compound")),
+    };
+    let now = env.block.time.seconds();
+
+    let mut basket = BASKETS.load(deps.storage, basket_name)?;
+    let token_prices = get_token_prices(deps.as_ref())?;
+    let mut holdings = BASKET_HOLDINGS.may_load(deps.storage, basket_name)?.unwrap_or_default();
+    let basket_value = holdings_value(&basket.tokens, &holdings, &token_prices)?;
+    let value = holdings_value(&basket.tokens, &received, &token_prices)?;
+    let shares = shares_for_value(&basket, basket_value, value)?;
+    for (symbol, amount) in received {
+        *holdings.entry(symbol).or_default() += amount;
+    }
+    BASKET_HOLDINGS.save(deps.storage, basket_name, &holdings)?;
+    basket.total_shares += shares;
+    basket.total_value_locked += value;
+    BASKETS.save(deps.storage, basket_name, &basket)?;
+
+    let total_credit: Uint128 = credits.iter().map(|(_, amount)| *amount).sum();
+    let (mut shares_left, mut value_left) = (shares, value);
+    for (i, (user, amount)) in credits.iter().enumerate() {
+        let (user_shares, user_value) = if i + 1 == credits.len() {
+            (shares_left, value_left)
+        } else {
+            (
+                shares.multiply_ratio(*amount, total_credit),
+                value.multiply_ratio(*amount, total_credit),
+            )
+        };
+        shares_left -= user_shares;
+        value_left -= user_value;
+
+        let mut portfolio = PORTFOLIOS.load(deps.storage, user)?;
+        settle_harvest_rewards(deps.as_ref(), &mut portfolio)?;
+        let position = portfolio
+            .positions
+            .iter_mut()
+            .find(|p| p.basket_name == basket_name)
+            .ok_or_else(|| StdError::generic_err("Position not found"))?;
+        position.shares += user_shares;
+        position.current_value += user_value;
+        position.last_updated = now;
+        portfolio.total_current_value += user_value;
+        portfolio.investment_history.push(InvestmentHistory {
+            timestamp: now,
+            action: InvestmentAction::Reinvest,
+            amount: user_value,
+            basket_name: basket_name.to_string(),
+            token_prices: token_prices.clone(),
+            swaps: swaps.clone(),
+        });
+        PORTFOLIOS.save(deps.storage, user, &portfolio)?;
+    }
+
+    CONFIG.update(deps.storage, |mut config| -> StdResult<_> {
+        config.total_value_locked += value;
+        Ok(config)
+    })?;
+
+    Ok(Response::new()
+        .add_attribute("compounded_value", value.to_string())
+        .add_attribute("compounded_shares", shares.to_string()))
+}
+
+/// Records oracle prices and when they were set, which exit triggers use to
+/// refuse stale prices.
+pub fn execute_update_prices(
+    deps: DepsMut<ArchwayQuery>,
+    env: Env,
+    info: MessageInfo,
+    prices: Vec<PriceUpdate>,
+) -> StdResult<Response<ArchwayMsg>> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin && config.oracle.as_deref() != Some(info.sender.as_str()) {
+        return Err(StdError::generic_err("Unauthorized"));
+    }
+
+    let now = env.block.time.seconds();
+    for update in &prices {
+        if update.price.is_zero() {
+            return Err(StdError::generic_err(format!("Price for {} must be greater than zero", update.symbol)));
+        }
+        PRICE_FEEDS.save(deps.storage, &update.symbol, &update.price)?;
+        PRICE_UPDATED.save(deps.storage, &update.symbol, &now)?;
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "update_prices")
+        .add_attribute("count", prices.len().to_string()))
+}
+
+pub fn execute_set_exit_triggers(
+    deps: DepsMut<ArchwayQuery>,
+    info: MessageInfo,
+    basket_name: String,
+    stop_loss: Option<ExitThreshold>,
+    take_profit: Option<ExitThreshold>,
+    destinations: Vec<ChainDestination>,
+) -> StdResult<Response<ArchwayMsg>> {
+    let owner = info.sender.to_string();
+    let shares = position_shares(deps.as_ref(), &owner, &basket_name)?;
+
+    if stop_loss.is_none() && take_profit.is_none() {
+        EXIT_TRIGGERS.remove(deps.storage, (&owner, &basket_name));
+        return Ok(Response::new()
+            .add_attribute("action", "clear_exit_triggers")
+            .add_attribute("basket", basket_name));
+    }
+    if let Some(ExitThreshold::Percent { bps }) = &stop_loss {
+        if *bps == 0 || *bps as u128 >= BPS_DENOMINATOR {
+            return Err(StdError::generic_err("Stop-loss must be between 0% and 100%"));
+        }
+    }
+    if let Some(ExitThreshold::Percent { bps: 0 }) = &take_profit {
+        return Err(StdError::generic_err("Take-profit must be above 0%"));
+    }
+
+    // Exits are executed by keepers, so every payout route must be known now.
+    let basket = BASKETS.load(deps.storage, &basket_name)?;
+    let resolved = resolve_destinations(deps.storage, destinations.clone())?;
+    let payouts = preview_payouts(deps.storage, &basket, shares)?;
+    check_payout_routes(deps.storage, &basket, &payouts, &resolved, None)?;
+
+    EXIT_TRIGGERS.save(
+        deps.storage,
+        (&owner, &basket_name),
+        &ExitTriggers {
+            stop_loss,
+            take_profit,
+            destinations,
+        },
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_exit_triggers")
+        .add_attribute("basket", basket_name))
+}
+
+/// Fails if any of `tokens` was last priced more than `price_max_age` ago.
+fn ensure_prices_fresh(storage: &dyn Storage, config: &Config, tokens: &[TokenWeight], now: u64) -> StdResult<()> {
+    for token in tokens {
+        let updated = PRICE_UPDATED.may_load(storage, &token.token.symbol)?.unwrap_or_default();
+        if now.saturating_sub(updated) > config.price_max_age {
+            return Err(StdError::generic_err(format!(
+                "Price for {} is stale",
+                token.token.symbol
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Permissionless: exits `owner`'s whole position once a stop-loss or
+/// take-profit is crossed at prices no older than `price_max_age`. The caller
+/// covers any bridge fees.
+pub fn execute_trigger_exit(
+    mut deps: DepsMut<ArchwayQuery>,
+    env: Env,
+    info: MessageInfo,
+    owner: String,
+    basket_name: String,
+) -> StdResult<Response<ArchwayMsg>> {
+    let config = CONFIG.load(deps.storage)?;
+    let triggers = EXIT_TRIGGERS
+        .may_load(deps.storage, (&owner, &basket_name))?
+        .ok_or_else(|| StdError::generic_err("No exit triggers set"))?;
+    let basket = BASKETS.load(deps.storage, &basket_name)?;
+    ensure_prices_fresh(deps.storage, &config, &basket.tokens, env.block.time.seconds())?;
+
+    let portfolio = PORTFOLIOS.load(deps.storage, &owner)?;
+    let position = portfolio
+        .positions
+        .iter()
+        .find(|p| p.basket_name == basket_name)
+        .ok_or_else(|| StdError::generic_err("Position not found"))?;
+    let prices = get_token_prices(deps.as_ref())?;
+    let holdings = BASKET_HOLDINGS.may_load(deps.storage, &basket_name)?.unwrap_or_default();
+    let market_value = holdings_value(&basket.tokens, &holdings, &prices)?
+        .multiply_ratio(position.shares, basket.total_shares);
+    let basis = position.initial_investment;
+    let shares = position.shares;
+
+    let crossed = |threshold: &ExitThreshold, below: bool| match threshold {
+        ExitThreshold::Percent { bps } => {
+            let bps = *bps as u128;
+            if below {
+                market_value <= basis.multiply_ratio(BPS_DENOMINATOR - bps, BPS_DENOMINATOR)
+            } else {
+                market_value >= basis.multiply_ratio(BPS_DENOMINATOR + bps, BPS_DENOMINATOR)
+            }
+        }
+        ExitThreshold::Value { value } => {
+            if below {
+                market_value <= *value
+            } else {
+                market_value >= *value
+            }
+        }
+    };
+    let action = if triggers.stop_loss.as_ref().is_some_and(|t| crossed(t, true)) {
+        InvestmentAction::StopLoss
+    } else if triggers.take_profit.as_ref().is_some_and(|t| crossed(t, false)) {
+        InvestmentAction::TakeProfit
+    } else {
+        return Err(StdError::generic_err("No exit threshold crossed"));
+    };
+
+    let destinations = resolve_destinations(deps.storage, triggers.destinations)?;
+    let payouts = preview_payouts(deps.storage, &basket, shares)?;
+    check_payout_routes(deps.storage, &basket, &payouts, &destinations, None)?;
+    let redemption = redeem_position(deps.branch(), &env, &owner, &basket_name, shares, action.clone())?;
+    let outflows = generate_withdraw_messages(
+        deps.storage,
+        &env,
+        &redemption.basket,
+        &owner,
+        None,
+        &destinations,
+        &redemption.payouts,
+    )?;
+    ensure_funds_match(&info.funds, &outflows.bridge_fees)?;
+    track_outflows(deps.storage, &outflows)?;
+
+    Ok(Response::new()
+        .add_submessages(outflows.messages)
+        .add_attribute("action", "trigger_exit")
+        .add_attribute("trigger", format!("{:?}", action))
+        .add_attribute("owner", owner)
+        .add_attribute("basket", basket_name)
+        .add_attribute("market_value", market_value.to_string())
+        .add_attribute("withdrawal_id", outflows.withdrawal.id.to_string()))
+}
+
+/// Escrows the single attached coin for a recurring investment. The escrow must
+/// be a priced token so each run can be valued. The first run is due immediately.
+#[allow(clippy::too_many_arguments)]
+pub fn execute_create_dca(
+    deps: DepsMut<ArchwayQuery>,
+    env: Env,
+    info: MessageInfo,
+    basket_name: String,
+    amount_per_run: Uint128,
+    interval: u64,
+    end: DcaEnd,
+    auto_compound: bool,
+) -> StdResult<Response<ArchwayMsg>> {
+    let escrow = match info.funds.as_slice() {
+        [coin] => coin.clone(),
+        _ => return Err(StdError::generic_err("DCA escrow must be exactly one coin")),
+    };
+    let basket = BASKETS.load(deps.storage, &basket_name)?;
+    if !basket.active {
+        return Err(StdError::generic_err("Basket is not active"));
+    }
+    if interval == 0 {
+        return Err(StdError::generic_err("DCA interval must be greater than zero"));
+    }
+    if amount_per_run.is_zero() || amount_per_run > escrow.amount {
+        return Err(StdError::generic_err("Amount per run must be between 1 and the escrow"));
+    }
+    let escrow_token = denom_token(deps.storage, &basket, &escrow.denom)?
+        .ok_or_else(|| StdError::generic_err(format!("{} is not a registered token", escrow.denom)))?;
+    token_price(&get_token_prices(deps.as_ref())?, &escrow_token.symbol)?;
+
+    let id = DCA_SEQ.may_load(deps.storage)?.unwrap_or_default() + 1;
+    DCA_SEQ.save(deps.storage, &id)?;
+    let schedule = DcaSchedule {
+        id,
+        owner: info.sender.to_string(),
+        basket_name,
+        escrow,
+        amount_per_run,
+        interval,
+        next_run: env.block.time.seconds(),
+        runs: 0,
+        end,
+        auto_compound,
+    };
+    DCA_SCHEDULES.save(deps.storage, id, &schedule)?;
+    DCA_QUEUE.save(deps.storage, (schedule.next_run, id), &())?;
 
     Ok(Response::new()
-        .add_messages(messages)
-        .add_attribute("action", "withdraw")
-        .add_attribute("amount", withdraw_amount.to_string()))
+        .add_attribute("action", "create_dca")
+        .add_attribute("id", id.to_string())
+        .add_attribute("basket", schedule.basket_name)
+        .add_attribute("escrow", schedule.escrow.to_string()))
 }
 
+pub fn execute_cancel_dca(
+    deps: DepsMut<ArchwayQuery>,
+    info: MessageInfo,
+    id: u64,
+) -> StdResult<Response<ArchwayMsg>> {
+    let schedule = DCA_SCHEDULES.load(deps.storage, id)?;
+    if schedule.owner != info.sender {
+        return Err(StdError::generic_err("Unauthorized"));
+    }
+    DCA_SCHEDULES.remove(deps.storage, id);
+    DCA_QUEUE.remove(deps.storage, (schedule.next_run, id));
 
-fn generate_withdraw_messages(
-    position: &InvestmentPosition,
-    token_prices: &HashMap<String, Uint128>,
-    percentage: u8,
-) -> StdResult<Vec<CosmosMsg>> {
-    let mut messages: Vec<CosmosMsg> = vec![];
-
-    for (token_symbol, amount) in &position.token_amounts {
-        let withdraw_amount = amount.multiply_ratio(percentage as u128, 100u128);
-        
-        // Get basket configuration to access token information
-        let basket = BASKETS.load(deps.storage, &position.basket_name)?;
-        let token = basket.tokens.iter()
-            .find(|t| t.token.symbol == *token_symbol)
-            .ok_or_else(|| StdError::generic_err("Token not found"))?;
+    let mut response = Response::new()
+        .add_attribute("action", "cancel_dca")
+        .add_attribute("id", id.to_string())
+        .add_attribute("refund", schedule.escrow.to_string());
+    if !schedule.escrow.amount.is_zero() {
+        response = response.add_message(BankMsg::Send {
+            to_address: schedule.owner,
+            amount: vec![schedule.escrow],
+        });
+    }
+    Ok(response)
+}
 
-        match token.token.chain {
-            ChainType::Cosmos => {
-                messages.push(CosmosMsg::Bank(BankMsg::Send {
-                    to_address: position.user.clone(),
-                    amount: vec![Coin {
-                        denom: token_symbol.clone(),
-                        amount: withdraw_amount,
-                    }],
-                }));
-            },
-            ChainType::Ethereum => {
-                messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
-                    contract_addr: "eth_bridge".to_string(),
-                    msg: to_binary(&"bridge_to_eth")?,
-                    funds: vec![Coin {
-                        denom: token_symbol.clone(),
-                        amount: withdraw_amount,
-                    }],
-                }));
-            },
-            _ => return Err(StdError::generic_err("Unsupported chain")),
+/// Runs up to `limit` due schedules, paying the caller `keeper_tip_bps` of
+/// each run. A run in a component denom is deposited in kind; any other denom
+/// is zapped in. Each run executes as its own submessage, so one that fails,
+/// for instance on slippage, is skipped like any other and the rest of the
+/// batch still goes through. Finished schedules refund what is left of their escrow.
+pub fn execute_run_dca(
+    deps: DepsMut<ArchwayQuery>,
+    env: Env,
+    info: MessageInfo,
+    limit: Option<u32>,
+) -> StdResult<Response<ArchwayMsg>> {
+    let config = CONFIG.load(deps.storage)?;
+    let token_prices = get_token_prices(deps.as_ref())?;
+    let now = env.block.time.seconds();
+    let limit = limit.unwrap_or(DEFAULT_DCA_LIMIT).min(MAX_DCA_LIMIT) as usize;
+
+    let due: Vec<(u64, u64)> = DCA_QUEUE
+        .keys(deps.storage, None, Some(Bound::inclusive((now, u64::MAX))), Order::Ascending)
+        .take(limit)
+        .collect::<StdResult<_>>()?;
+
+    let mut refunds: Vec<BankMsg> = vec![];
+    let mut runs: Vec<SubMsg<ArchwayMsg>> = vec![];
+    let mut run_ids: Vec<u64> = vec![];
+    let mut skipped = 0u32;
+
+    for (next_run, id) in due {
+        let mut schedule = DCA_SCHEDULES.load(deps.storage, id)?;
+        DCA_QUEUE.remove(deps.storage, (next_run, id));
+
+        if !schedule.is_finished(now) {
+            let basket = BASKETS.load(deps.storage, &schedule.basket_name)?;
+            let in_kind = is_component(&basket, &schedule.escrow.denom);
+            let tip = schedule
+                .amount_per_run
+                .multiply_ratio(config.keeper_tip_bps as u128, BPS_DENOMINATOR);
+            // A zapped run must still clear the minimum after worst-case slippage.
+            let run_value = match denom_token(deps.storage, &basket, &schedule.escrow.denom)? {
+                Some(token) => match token_price(&token_prices, &token.symbol) {
+                    Ok(price) => {
+                        let value = token_value(schedule.amount_per_run - tip, price, token.decimals)?;
+                        Some(if in_kind {
+                            value
+                        } else {
+                            let slippage = BPS_DENOMINATOR - config.max_slippage_bps as u128;
+                            value.multiply_ratio(slippage, BPS_DENOMINATOR)
+                        })
+                    }
+                    Err(_) => None,
+                },
+                None => None,
+            };
+
+            // A paused basket, an unpriced escrow or a run too small for the
+            // basket skips this interval instead of failing the whole batch.
+            match run_value {
+                Some(value) if basket.active && value >= basket.min_investment => {
+                    let msg = WasmMsg::Execute {
+                        contract_addr: env.contract.address.to_string(),
+                        msg: to_json_binary(&ExecuteMsg::ExecuteDcaRun {
+                            id,
+                            keeper: info.sender.to_string(),
+                            min_value: value,
+                        })?,
+                        funds: vec![],
+                    };
+                    runs.push(SubMsg::reply_always(msg, DCA_RUN_REPLY_ID));
+                    run_ids.push(id);
+                }
+                _ => skipped += 1,
+            }
+            schedule.next_run = next_run.max(now) + schedule.interval;
         }
+
+        if schedule.is_finished(now) {
+            DCA_SCHEDULES.remove(deps.storage, id);
+            if !schedule.escrow.amount.is_zero() {
+                refunds.push(BankMsg::Send {
+                    to_address: schedule.owner,
+                    amount: vec![schedule.escrow],
+                });
+            }
+        } else {
+            DCA_SCHEDULES.save(deps.storage, id, &schedule)?;
+            DCA_QUEUE.save(deps.storage, (schedule.next_run, id), &())?;
+        }
+    }
+    if !run_ids.is_empty() {
+        DCA_RUN_QUEUE.save(deps.storage, &run_ids)?;
     }
 
-    Ok(messages)
+    Ok(Response::new()
+        .add_messages(refunds)
+        .add_submessages(runs)
+        .add_attribute("action", "run_dca")
+        .add_attribute("dispatched", run_ids.len().to_string())
+        .add_attribute("skipped", skipped.to_string()))
 }
-pub fn execute_claim_rewards(
-    deps: DepsMut,
+
+/// Invests one run of a schedule that `RunDca` already rescheduled, and pays
+/// `keeper` its tip. Only callable by the contract itself, so a failing run
+/// reverts as a unit and leaves its escrow untouched.
+pub fn execute_dca_run(
+    mut deps: DepsMut<ArchwayQuery>,
     env: Env,
     info: MessageInfo,
-) -> StdResult<Response> {
+    id: u64,
+    keeper: String,
+    min_value: Uint128,
+) -> StdResult<Response<ArchwayMsg>> {
+    if info.sender != env.contract.address {
+        return Err(StdError::generic_err("Unauthorized"));
+    }
     let config = CONFIG.load(deps.storage)?;
-    let mut portfolio = PORTFOLIOS.load(deps.storage, info.sender.as_str())?;
+    let mut schedule = DCA_SCHEDULES.load(deps.storage, id)?;
+    let basket = BASKETS.load(deps.storage, &schedule.basket_name)?;
+    let tip = schedule
+        .amount_per_run
+        .multiply_ratio(config.keeper_tip_bps as u128, BPS_DENOMINATOR);
+    let invest = Coin {
+        denom: schedule.escrow.denom.clone(),
+        amount: schedule.amount_per_run - tip,
+    };
 
-    let rewards = calculate_rewards(&portfolio, &config, env.block.time.seconds())?;
-    if rewards == Uint128::zero() {
-        return Err(StdError::generic_err("No rewards to claim"));
+    let mut response = if is_component(&basket, &invest.denom) {
+        let token = denom_token(deps.storage, &basket, &invest.denom)?
+            .ok_or_else(|| StdError::generic_err(format!("{} is not a registered token", invest.denom)))?;
+        let deposited = HashMap::from([(token.symbol, invest.amount)]);
+        let (shares, value) = deposit_components(
+            deps.branch(),
+            &env,
+            &schedule.owner,
+            basket,
+            deposited,
+            min_value,
+            schedule.auto_compound,
+            vec![],
+        )?;
+        Response::new()
+            .add_attribute("shares", shares.to_string())
+            .add_attribute("value", value.to_string())
+    } else {
+        zap_into_basket(
+            deps.branch(),
+            env.clone(),
+            schedule.owner.clone(),
+            schedule.basket_name.clone(),
+            invest,
+            min_value,
+            schedule.auto_compound,
+        )?
+    };
+
+    schedule.escrow.amount -= schedule.amount_per_run;
+    schedule.runs += 1;
+    if schedule.is_finished(env.block.time.seconds()) {
+        DCA_SCHEDULES.remove(deps.storage, id);
+        DCA_QUEUE.remove(deps.storage, (schedule.next_run, id));
+        if !schedule.escrow.amount.is_zero() {
+            response = response.add_message(BankMsg::Send {
+                to_address: schedule.owner.clone(),
+                amount: vec![schedule.escrow.clone()],
+            });
+        }
+    } else {
+        DCA_SCHEDULES.save(deps.storage, id, &schedule)?;
+    }
+    if !tip.is_zero() {
+        response = response.add_message(BankMsg::Send {
+            to_address: keeper,
+            amount: vec![Coin {
+                denom: schedule.escrow.denom,
+                amount: tip,
+            }],
+        });
     }
 
-    portfolio.rewards_earned += rewards;
-    portfolio.last_claim = env.block.time.seconds();
-    
-    PORTFOLIOS.save(deps.storage, info.sender.as_str(), &portfolio)?;
+    Ok(response
+        .add_attribute("action", "dca_run")
+        .add_attribute("id", id.to_string())
+        .add_attribute("runs", schedule.runs.to_string()))
+}
+
+/// Counts a failed run as skipped. Its escrow is untouched and it was already
+/// rescheduled by `RunDca`.
+fn reply_dca_run(deps: DepsMut<ArchwayQuery>, msg: Reply) -> StdResult<Response<ArchwayMsg>> {
+    let mut queue = DCA_RUN_QUEUE.load(deps.storage)?;
+    if queue.is_empty() {
+        return Err(StdError::generic_err("No DCA run awaiting a reply"));
+    }
+    let id = queue.remove(0);
+    if queue.is_empty() {
+        DCA_RUN_QUEUE.remove(deps.storage);
+    } else {
+        DCA_RUN_QUEUE.save(deps.storage, &queue)?;
+    }
+
+    let response = Response::new().add_attribute("id", id.to_string());
+    match msg.result {
+        SubMsgResult::Ok(_) => Ok(response.add_attribute("action", "dca_run_executed")),
+        SubMsgResult::Err(err) => Ok(response
+            .add_attribute("action", "dca_run_skipped")
+            .add_attribute("skipped", "1")
+            .add_attribute("error", err)),
+    }
+}
+
+/// Withdraws the contract's accumulated Archway rewards records. The withdrawn
+/// coins are split between the treasury and basket holders in the reply.
+pub fn execute_harvest_archway_rewards(
+    deps: DepsMut<ArchwayQuery>,
+    env: Env,
+    info: MessageInfo,
+    limit: Option<u64>,
+) -> StdResult<Response<ArchwayMsg>> {
+    let config = CONFIG.load(deps.storage)?;
+    let sender = info.sender.to_string();
+    if sender != config.admin && !config.keepers.contains(&sender) {
+        return Err(StdError::generic_err("Unauthorized"));
+    }
 
-    let transfer_msg = create_reward_transfer_msg(info.sender.to_string(), rewards, &config)?;
+    let query = match limit {
+        Some(limit) => ArchwayQuery::rewards_records_with_pagination(
+            env.contract.address.to_string(),
+            PageRequest::new().limit(limit),
+        ),
+        None => ArchwayQuery::rewards_records(env.contract.address.to_string()),
+    };
+    let pending: RewardsRecordsResponse = deps.querier.query(&query.into())?;
+    if pending.records.is_empty() {
+        return Err(StdError::generic_err("No rewards records to withdraw"));
+    }
+
+    let record_ids: Vec<u64> = pending.records.iter().map(|r| r.id).collect();
+    let records_num = record_ids.len();
 
     Ok(Response::new()
-        .add_message(transfer_msg)
-        .add_attribute("action", "claim_rewards")
-        .add_attribute("amount", rewards.to_string()))
+        .add_submessage(SubMsg::reply_on_success(
+            ArchwayMsg::withdraw_rewards_by_ids(record_ids),
+            HARVEST_REPLY_ID,
+        ))
+        .add_attribute("action", "harvest_archway_rewards")
+        .add_attribute("records", records_num.to_string()))
 }
 
-pub fn execute_set_auto_compound(
-    deps: DepsMut,
+fn reply_harvest_archway_rewards(
+    deps: DepsMut<ArchwayQuery>,
+    _env: Env,
+    msg: Reply,
+) -> StdResult<Response<ArchwayMsg>> {
+    let data = msg
+        .result
+        .into_result()
+        .map_err(StdError::generic_err)?
+        .data
+        .ok_or_else(|| StdError::generic_err("Missing rewards withdrawal data"))?;
+    let withdrawn: WithdrawRewardsResponse = from_json(&data)?;
+
+    let config = CONFIG.load(deps.storage)?;
+    let mut to_treasury: Vec<Coin> = vec![];
+
+    let baskets: Vec<BasketConfig> = BASKETS
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_, basket)| basket))
+        .filter(|item| item.as_ref().map_or(true, |b| !b.total_shares.is_zero() && !b.total_value_locked.is_zero()))
+        .collect::<StdResult<_>>()?;
+    let total_value: Uint128 = baskets.iter().map(|b| b.total_value_locked).sum();
+
+    for coin in withdrawn.total_rewards {
+        let treasury_amount = if total_value.is_zero() {
+            // Nobody to distribute to yet, so the whole amount goes to the treasury.
+            coin.amount
+        } else {
+            coin.amount.multiply_ratio(config.harvest_treasury_bps as u128, BPS_DENOMINATOR)
+        };
+        let holder_amount = coin.amount - treasury_amount;
+
+        // Each basket gets its share of locked value, spread over its shares;
+        // the last one takes the rounding remainder.
+        let mut left = holder_amount;
+        for (i, basket) in baskets.iter().enumerate() {
+            let amount = if i + 1 == baskets.len() {
+                left
+            } else {
+                holder_amount.multiply_ratio(basket.total_value_locked, total_value)
+            };
+            left -= amount;
+            if amount.is_zero() {
+                continue;
+            }
+            let increment = Decimal::from_ratio(amount, basket.total_shares);
+            HARVEST_INDEX.update(deps.storage, (&basket.name, &coin.denom), |index| -> StdResult<_> {
+                Ok(index.unwrap_or_else(Decimal::zero) + increment)
+            })?;
+        }
+        if !treasury_amount.is_zero() {
+            to_treasury.push(Coin {
+                denom: coin.denom,
+                amount: treasury_amount,
+            });
+        }
+    }
+
+    let mut response = Response::new()
+        .add_attribute("action", "distribute_archway_rewards")
+        .add_attribute("records", withdrawn.records_num.to_string());
+    if !to_treasury.is_empty() {
+        response = response.add_message(BankMsg::Send {
+            to_address: config.treasury,
+            amount: to_treasury,
+        });
+    }
+
+    Ok(response)
+}
+
+/// Updates the contract's Archway rewards metadata. Pointing the rewards address
+/// away from this contract stops `HarvestArchwayRewards` from finding records.
+pub fn execute_update_archway_metadata(
+    deps: DepsMut<ArchwayQuery>,
     info: MessageInfo,
-    basket_name: String,
-    enabled: bool,
-) -> StdResult<Response> {
-    let mut portfolio = PORTFOLIOS.load(deps.storage, info.sender.as_str())?;
-    let position = portfolio.positions
-        .iter_mut()
-        .find(|p| p.basket_name == basket_name)
-        .ok_or_else(|| StdError::generic_err("Position not found"))?;
+    owner_address: Option<String>,
+    rewards_address: Option<String>,
+) -> StdResult<Response<ArchwayMsg>> {
+    let mut config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(StdError::generic_err("Unauthorized"));
+    }
+    if owner_address.is_none() && rewards_address.is_none() {
+        return Err(StdError::generic_err("Nothing to update"));
+    }
 
-    position.auto_compound = enabled;
-    PORTFOLIOS.save(deps.storage, info.sender.as_str(), &portfolio)?;
+    let mut messages: Vec<CosmosMsg<ArchwayMsg>> = vec![];
+    if let Some(owner) = owner_address {
+        let owner = deps.api.addr_validate(&owner)?.to_string();
+        messages.push(ArchwayMsg::update_rewards_ownership(owner.clone()).into());
+        config.archway_owner_address = Some(owner);
+    }
+    if let Some(rewards) = rewards_address {
+        let rewards = deps.api.addr_validate(&rewards)?.to_string();
+        messages.push(ArchwayMsg::update_rewards_address(rewards.clone()).into());
+        config.archway_rewards_address = Some(rewards);
+    }
+
+    CONFIG.save(deps.storage, &config)?;
 
     Ok(Response::new()
-        .add_attribute("action", "set_auto_compound")
-        .add_attribute("basket", basket_name)
-        .add_attribute("enabled", enabled.to_string()))
+        .add_messages(messages)
+        .add_attribute("action", "update_archway_metadata"))
+}
+
+/// Sets the flat fee (contract premium) charged on every call to this contract.
+pub fn execute_set_archway_flat_fee(
+    deps: DepsMut<ArchwayQuery>,
+    env: Env,
+    info: MessageInfo,
+    amount: Coin,
+) -> StdResult<Response<ArchwayMsg>> {
+    let mut config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(StdError::generic_err("Unauthorized"));
+    }
+
+    config.archway_flat_fee = Some(amount.clone());
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new()
+        .add_message(ArchwayMsg::set_flat_fee(env.contract.address, amount.clone()))
+        .add_attribute("action", "set_archway_flat_fee")
+        .add_attribute("flat_fee", amount.to_string()))
 }
 
+/// Current harvest index of `basket_name`, keyed by denom.
+fn harvest_index(storage: &dyn Storage, basket_name: &str) -> StdResult<HashMap<String, Decimal>> {
+    HARVEST_INDEX
+        .prefix(basket_name)
+        .range(storage, None, None, Order::Ascending)
+        .collect()
+}
+
+/// Moves harvested rewards each position accrued since its last checkpoint
+/// into the portfolio's pending balance. Must run before any position's
+/// shares change.
+fn settle_harvest_rewards(deps: Deps<ArchwayQuery>, portfolio: &mut UserPortfolio) -> StdResult<()> {
+    for position in portfolio.positions.iter_mut() {
+        for (denom, index) in harvest_index(deps.storage, &position.basket_name)? {
+            let checkpoint = position
+                .harvest_checkpoints
+                .get(&denom)
+                .copied()
+                .unwrap_or_else(Decimal::zero);
+
+            let accrued = position.shares * (index - checkpoint);
+            if !accrued.is_zero() {
+                *portfolio.harvest_pending.entry(denom.clone()).or_default() += accrued;
+            }
+            position.harvest_checkpoints.insert(denom, index);
+        }
+    }
+    Ok(())
+}
 
+/// Rebalances a basket's pooled holdings back to its target weights. Anyone may
+/// call it once a component drifts past the basket's band or the rebalance
+/// interval has elapsed.
 pub fn execute_rebalance(
-    deps: DepsMut,
+    deps: DepsMut<ArchwayQuery>,
     env: Env,
     info: MessageInfo,
     basket_name: String,
-) -> StdResult<Response> {
-    let basket = BASKETS.load(deps.storage, &basket_name)?;
-    let mut portfolio = PORTFOLIOS.load(deps.storage, info.sender.as_str())?;
-    let position = portfolio.positions
-        .iter_mut()
-        .find(|p| p.basket_name == basket_name)
-        .ok_or_else(|| StdError::generic_err("Position not found"))?;
-
+) -> StdResult<Response<ArchwayMsg>> {
+    let config = CONFIG.load(deps.storage)?;
+    let mut basket = BASKETS.load(deps.storage, &basket_name)?;
+    let holdings = BASKET_HOLDINGS.may_load(deps.storage, &basket_name)?.unwrap_or_default();
     let token_prices = get_token_prices(deps.as_ref())?;
-    let messages = generate_rebalance_messages(position, &basket.tokens, &token_prices)?;
+    let now = env.block.time.seconds();
 
-    position.last_updated = env.block.time.seconds();
-    portfolio.investment_history.push(InvestmentHistory {
-        timestamp: env.block.time.seconds(),
-        action: InvestmentAction::Rebalance,
-        amount: position.current_value,
-        basket_name,
-        token_prices,
-    });
+    let drift = component_drift(&holdings, &basket.tokens, &token_prices)?;
+    let max_drift = drift.iter().map(|d| d.drift_bps.unsigned_abs()).max().unwrap_or(0);
+    let scheduled = basket.rebalance_interval > 0
+        && now.saturating_sub(basket.last_rebalanced) >= basket.rebalance_interval;
+    if max_drift <= basket.drift_threshold_bps as u32 && !scheduled {
+        return Err(StdError::generic_err("Basket is within its drift band"));
+    }
+
+    let swaps = plan_rebalance_swaps(
+        &holdings,
+        &basket.tokens,
+        &token_prices,
+        config.max_slippage_bps,
+    )?;
+    let swap_count = swaps.len();
+
+    basket.last_rebalanced = now;
+    BASKETS.save(deps.storage, &basket_name, &basket)?;
 
-    PORTFOLIOS.save(deps.storage, info.sender.as_str(), &portfolio)?;
+    let swap_msg = dispatch_swap(
+        deps,
+        &env,
+        SwapJobKind::Rebalance,
+        info.sender.to_string(),
+        basket_name.clone(),
+        swaps,
+    )?;
 
     Ok(Response::new()
-        .add_messages(messages)
+        .add_submessages(swap_msg)
         .add_attribute("action", "rebalance")
-        .add_attribute("basket", basket_name))
+        .add_attribute("basket", basket_name)
+        .add_attribute("max_drift_bps", max_drift.to_string())
+        .add_attribute("swaps", swap_count.to_string()))
+}
+
+/// Current versus target weight of every basket component, in basis points.
+fn component_drift(
+    holdings: &HashMap<String, Uint128>,
+    tokens: &[TokenWeight],
+    prices: &HashMap<String, Uint128>,
+) -> StdResult<Vec<ComponentDrift>> {
+    let total_weight: u128 = tokens.iter().map(|t| t.weight as u128).sum();
+    let total_value = holdings_value(tokens, holdings, prices)?;
+
+    tokens
+        .iter()
+        .map(|token| -> StdResult<ComponentDrift> {
+            let symbol = &token.token.symbol;
+            let amount = holdings.get(symbol).copied().unwrap_or_default();
+            let value = token_value(amount, token_price(prices, symbol)?, token.token.decimals)?;
+            let current_weight_bps = if total_value.is_zero() {
+                0
+            } else {
+                value.multiply_ratio(BPS_DENOMINATOR, total_value).u128() as u32
+            };
+            let target_weight_bps =
+                (token.weight as u128 * BPS_DENOMINATOR / total_weight.max(1)) as u32;
+
+            Ok(ComponentDrift {
+                symbol: symbol.clone(),
+                value,
+                current_weight_bps,
+                target_weight_bps,
+                drift_bps: current_weight_bps as i32 - target_weight_bps as i32,
+            })
+        })
+        .collect()
+}
+
+/// Values each component at current prices and pairs overweight components
+/// with underweight ones until every holding matches its target weight.
+fn plan_rebalance_swaps(
+    token_amounts: &HashMap<String, Uint128>,
+    tokens: &[TokenWeight],
+    prices: &HashMap<String, Uint128>,
+    max_slippage_bps: u16,
+) -> StdResult<Vec<SwapLeg>> {
+    let total_weight: u128 = tokens.iter().map(|t| t.weight as u128).sum();
+
+    let mut valued = Vec::with_capacity(tokens.len());
+    for token in tokens {
+        let price = token_price(prices, &token.token.symbol)?;
+        let amount = token_amounts.get(&token.token.symbol).copied().unwrap_or_default();
+        valued.push((token, price, token_value(amount, price, token.token.decimals)?));
+    }
+    let total_value: Uint128 = valued.iter().map(|(_, _, value)| *value).sum();
+
+    let mut surpluses = vec![];
+    let mut deficits = vec![];
+    for (token, price, value) in valued {
+        let target = total_value.multiply_ratio(token.weight as u128, total_weight);
+        if value > target {
+            surpluses.push((token, price, value - target));
+        } else if target > value {
+            deficits.push((token, price, target - value));
+        }
+    }
+
+    let mut legs = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < surpluses.len() && j < deficits.len() {
+        let (seller, sell_price, surplus) = surpluses[i];
+        let (buyer, buy_price, deficit) = deficits[j];
+        let trade_value = surplus.min(deficit);
+
+        let offer_amount = token_quantity(trade_value, sell_price, seller.token.decimals)?;
+        let expected = token_quantity(trade_value, buy_price, buyer.token.decimals)?;
+        if !offer_amount.is_zero() && !expected.is_zero() {
+            legs.push(SwapLeg {
+                offer_symbol: seller.token.symbol.clone(),
+                offer: Coin {
+                    denom: seller.token.address.clone(),
+                    amount: offer_amount,
+                },
+                ask_symbol: buyer.token.symbol.clone(),
+                ask_denom: buyer.token.address.clone(),
+                expected,
+                min_output: expected.multiply_ratio(
+                    BPS_DENOMINATOR - max_slippage_bps as u128,
+                    BPS_DENOMINATOR,
+                ),
+            });
+        }
+
+        surpluses[i].2 = surplus - trade_value;
+        deficits[j].2 = deficit - trade_value;
+        if surpluses[i].2.is_zero() {
+            i += 1;
+        }
+        if deficits[j].2.is_zero() {
+            j += 1;
+        }
+    }
+
+    Ok(legs)
+}
+
+/// Sends the first of `legs` to the DEX router and stores the rest as the
+/// in-flight swap job. Returns `None` once there is nothing left to swap.
+fn dispatch_swap(
+    deps: DepsMut<ArchwayQuery>,
+    env: &Env,
+    kind: SwapJobKind,
+    user: String,
+    basket_name: String,
+    mut legs: Vec<SwapLeg>,
+) -> StdResult<Option<SubMsg<ArchwayMsg>>> {
+    if legs.is_empty() {
+        SWAP_JOB.remove(deps.storage);
+        return Ok(None);
+    }
+
+    let router = CONFIG
+        .load(deps.storage)?
+        .dex_router
+        .ok_or_else(|| StdError::generic_err("DEX router not configured"))?;
+
+    let current = legs.remove(0);
+    let ask_balance_before = deps
+        .querier
+        .query_balance(&env.contract.address, &current.ask_denom)?
+        .amount;
+
+    let msg = WasmMsg::Execute {
+        contract_addr: router,
+        msg: to_json_binary(&RouterExecuteMsg::Swap {
+            ask_denom: current.ask_denom.clone(),
+            minimum_receive: current.min_output,
+        })?,
+        funds: vec![current.offer.clone()],
+    };
+
+    SWAP_JOB.save(
+        deps.storage,
+        &SwapJob {
+            kind,
+            user,
+            basket_name,
+            current,
+            ask_balance_before,
+            remaining: legs,
+        },
+    )?;
+
+    Ok(Some(SubMsg::reply_on_success(msg, SWAP_REPLY_ID)))
+}
+
+fn reply_swap(
+    mut deps: DepsMut<ArchwayQuery>,
+    env: Env,
+    _msg: Reply,
+) -> StdResult<Response<ArchwayMsg>> {
+    let job = SWAP_JOB.load(deps.storage)?;
+
+    let balance = deps
+        .querier
+        .query_balance(&env.contract.address, &job.current.ask_denom)?
+        .amount;
+    let received = balance.checked_sub(job.ask_balance_before)?;
+    if received < job.current.min_output {
+        return Err(StdError::generic_err(format!(
+            "Swap into {} returned {}, below minimum {}",
+            job.current.ask_symbol, received, job.current.min_output
+        )));
+    }
+
+    let kind = match job.kind {
+        SwapJobKind::Rebalance => {
+            apply_rebalance_swap(deps.branch(), &job, received)?;
+            SwapJobKind::Rebalance
+        }
+        SwapJobKind::ZapOut { output_symbol, min_output, received: total, mut swaps } => {
+            swaps.push(SwapRecord::new(&job.current, received));
+            SwapJobKind::ZapOut {
+                output_symbol,
+                min_output,
+                received: total + received,
+                swaps,
+            }
+        }
+        SwapJobKind::ZapIn { min_value, auto_compound, received: mut totals, mut swaps } => {
+            swaps.push(SwapRecord::new(&job.current, received));
+            *totals.entry(job.current.ask_symbol.clone()).or_default() += received;
+            SwapJobKind::ZapIn {
+                min_value,
+                auto_compound,
+                received: totals,
+                swaps,
+            }
+        }
+        SwapJobKind::Compound { credits, received: mut totals, mut swaps } => {
+            swaps.push(SwapRecord::new(&job.current, received));
+            *totals.entry(job.current.ask_symbol.clone()).or_default() += received;
+            SwapJobKind::Compound {
+                credits,
+                received: totals,
+                swaps,
+            }
+        }
+    };
+
+    let response = Response::new()
+        .add_attribute("action", "swap")
+        .add_attribute("offer", job.current.offer.to_string())
+        .add_attribute("ask_denom", &job.current.ask_denom)
+        .add_attribute("received", received.to_string());
+
+    let next = dispatch_swap(
+        deps.branch(),
+        &env,
+        kind.clone(),
+        job.user.clone(),
+        job.basket_name.clone(),
+        job.remaining,
+    )?;
+    match (next, kind) {
+        (Some(swap_msg), _) => Ok(response.add_submessage(swap_msg)),
+        (None, kind @ SwapJobKind::ZapOut { .. }) => {
+            let payout = finish_zap_out(deps, &job.user, &job.basket_name, kind)?;
+            Ok(response
+                .add_submessages(payout.messages)
+                .add_attributes(payout.attributes))
+        }
+        (None, kind @ SwapJobKind::ZapIn { .. }) => {
+            let credited = finish_zap_in(deps, &env, &job.user, &job.basket_name, kind)?;
+            Ok(response.add_attributes(credited.attributes))
+        }
+        (None, kind @ SwapJobKind::Compound { .. }) => {
+            let credited = finish_compound(deps, &env, &job.basket_name, kind)?;
+            Ok(response.add_attributes(credited.attributes))
+        }
+        (None, SwapJobKind::Rebalance) => Ok(response),
+    }
+}
+
+fn apply_rebalance_swap(
+    deps: DepsMut<ArchwayQuery>,
+    job: &SwapJob,
+    received: Uint128,
+) -> StdResult<()> {
+    let mut holdings = BASKET_HOLDINGS.load(deps.storage, &job.basket_name)?;
+
+    let offered = holdings.entry(job.current.offer_symbol.clone()).or_default();
+    *offered = offered.checked_sub(job.current.offer.amount)?;
+    *holdings.entry(job.current.ask_symbol.clone()).or_default() += received;
+
+    BASKET_HOLDINGS.save(deps.storage, &job.basket_name, &holdings)
 }
 
 pub fn execute_update_config(
-    deps: DepsMut,
+    deps: DepsMut<ArchwayQuery>,
     info: MessageInfo,
-    reward_rate: Option<Uint128>,
-    min_lock_period: Option<u64>,
-    compound_frequency: Option<u64>,
-) -> StdResult<Response> {
+    update: ConfigUpdate,
+) -> StdResult<Response<ArchwayMsg>> {
     let mut config = CONFIG.load(deps.storage)?;
-    if info.sender.to_string() != config.admin {
+    if info.sender != config.admin {
+        return Err(StdError::generic_err("Unauthorized"));
+    }
+
+    if let Some(rate) = update.reward_rate {
+        config.reward_rate = rate;
+    }
+    if let Some(period) = update.min_lock_period {
+        config.min_lock_period = period;
+    }
+    if let Some(frequency) = update.compound_frequency {
+        config.compound_frequency = frequency;
+    }
+    if let Some(tip) = update.keeper_tip_bps {
+        if tip as u128 > BPS_DENOMINATOR {
+            return Err(StdError::generic_err("Keeper tip exceeds 100%"));
+        }
+        config.keeper_tip_bps = tip;
+    }
+    if let Some(keepers) = update.keepers {
+        for keeper in &keepers {
+            deps.api.addr_validate(keeper)?;
+        }
+        config.keepers = keepers;
+    }
+    if let Some(treasury) = update.treasury {
+        config.treasury = deps.api.addr_validate(&treasury)?.to_string();
+    }
+    if let Some(bps) = update.harvest_treasury_bps {
+        if bps as u128 > BPS_DENOMINATOR {
+            return Err(StdError::generic_err("Treasury share exceeds 100%"));
+        }
+        config.harvest_treasury_bps = bps;
+    }
+    if let Some(router) = update.dex_router {
+        config.dex_router = Some(deps.api.addr_validate(&router)?.to_string());
+    }
+    if let Some(seconds) = update.ibc_timeout_seconds {
+        config.ibc_timeout_seconds = seconds;
+    }
+    if let Some(oracle) = update.oracle {
+        config.oracle = Some(deps.api.addr_validate(&oracle)?.to_string());
+    }
+    if let Some(seconds) = update.price_max_age {
+        config.price_max_age = seconds;
+    }
+    if let Some(bps) = update.max_slippage_bps {
+        if bps as u128 > BPS_DENOMINATOR {
+            return Err(StdError::generic_err("Slippage limit exceeds 100%"));
+        }
+        config.max_slippage_bps = bps;
+    }
+
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new().add_attribute("action", "update_config"))
+}
+
+pub fn execute_set_chain(
+    deps: DepsMut<ArchwayQuery>,
+    info: MessageInfo,
+    chain: ChainInfo,
+) -> StdResult<Response<ArchwayMsg>> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(StdError::generic_err("Unauthorized"));
+    }
+    if chain.id.is_empty() {
+        return Err(StdError::generic_err("Chain id cannot be empty"));
+    }
+    if let AddressFormat::Bech32 { prefix } = &chain.address_format {
+        if prefix.is_empty() {
+            return Err(StdError::generic_err("Bech32 prefix cannot be empty"));
+        }
+    }
+
+    let bridge = chain
+        .bridge
+        .map(|bridge| -> StdResult<BridgeConfig> {
+            Ok(BridgeConfig {
+                contract: deps.api.addr_validate(&bridge.contract)?.to_string(),
+                ..bridge
+            })
+        })
+        .transpose()?;
+    let chain = ChainInfo { bridge, ..chain };
+    CHAINS.save(deps.storage, &chain.id, &chain)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_chain")
+        .add_attribute("chain", &chain.id)
+        .add_attribute(
+            "bridge",
+            chain.bridge.as_ref().map_or("none", |b| b.contract.as_str()),
+        )
+        .add_attribute("enabled", chain.enabled.to_string()))
+}
+
+/// Only chains no basket references can be removed; disable the others instead.
+pub fn execute_remove_chain(
+    deps: DepsMut<ArchwayQuery>,
+    info: MessageInfo,
+    id: String,
+) -> StdResult<Response<ArchwayMsg>> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
         return Err(StdError::generic_err("Unauthorized"));
     }
 
-    if let Some(rate) = reward_rate {
-        config.reward_rate = rate;
-    }
-    if let Some(period) = min_lock_period {
-        config.min_lock_period = period;
-    }
-    if let Some(frequency) = compound_frequency {
-        config.compound_frequency = frequency;
+    for basket in BASKETS.range(deps.storage, None, None, Order::Ascending) {
+        let (name, basket) = basket?;
+        if basket.tokens.iter().any(|t| t.token.chain == id) {
+            return Err(StdError::generic_err(format!(
+                "Chain {} is used by basket {}",
+                id, name
+            )));
+        }
     }
+    CHAINS.remove(deps.storage, &id);
 
-    CONFIG.save(deps.storage, &config)?;
+    Ok(Response::new()
+        .add_attribute("action", "remove_chain")
+        .add_attribute("chain", id))
+}
 
-    Ok(Response::new().add_attribute("action", "update_config"))
+fn token_price(prices: &HashMap<String, Uint128>, symbol: &str) -> StdResult<Uint128> {
+    prices
+        .get(symbol)
+        .copied()
+        .filter(|price| !price.is_zero())
+        .ok_or_else(|| StdError::generic_err(format!("Missing price for {}", symbol)))
 }
 
-fn calculate_token_amounts(
-    tokens: &[TokenWeight],
-    amount: Uint128,
-    prices: &HashMap<String, Uint128>,
-) -> StdResult<HashMap<String, Uint128>> {
-    let mut amounts = HashMap::new();
-    let total_weight: u8 = tokens.iter().map(|t| t.weight).sum();
+fn find_token<'a>(tokens: &'a [TokenWeight], symbol: &str) -> StdResult<&'a Token> {
+    tokens
+        .iter()
+        .map(|t| &t.token)
+        .find(|t| t.symbol == symbol)
+        .ok_or_else(|| StdError::generic_err(format!("Token {} is not in the basket", symbol)))
+}
 
-    for token in tokens {
-        let token_amount = amount.multiply_ratio(token.weight as u128, total_weight as u128);
-        amounts.insert(token.token.symbol.clone(), token_amount);
-    }
+/// Value of `amount` base units of a token with `decimals`, in `VALUE_DECIMALS` units.
+/// The intermediate product is kept in 256 bits so 18-decimal amounts cannot overflow.
+fn token_value(amount: Uint128, price: Uint128, decimals: u8) -> StdResult<Uint128> {
+    let numerator = amount.full_mul(price) * Uint256::from(10u128.pow(VALUE_DECIMALS));
+    let denominator = Uint256::from(10u128).pow(decimals as u32) * Uint256::from(PRICE_PRECISION);
+    Ok(Uint128::try_from(numerator / denominator)?)
+}
 
-    Ok(amounts)
+/// Base units of a token with `decimals` worth `value`; the inverse of `token_value`.
+fn token_quantity(value: Uint128, price: Uint128, decimals: u8) -> StdResult<Uint128> {
+    if price.is_zero() {
+        return Err(StdError::generic_err("Price must be greater than zero"));
+    }
+    let numerator = value.full_mul(PRICE_PRECISION) * Uint256::from(10u128).pow(decimals as u32);
+    let denominator = Uint256::from(price) * Uint256::from(10u128.pow(VALUE_DECIMALS));
+    Ok(Uint128::try_from(numerator / denominator)?)
 }
 
-fn get_token_prices(deps: Deps) -> StdResult<HashMap<String, Uint128>> {
+fn get_token_prices(deps: Deps<ArchwayQuery>) -> StdResult<HashMap<String, Uint128>> {
     let mut prices = HashMap::new();
     for item in PRICE_FEEDS.range(deps.storage, None, None, Order::Ascending) {
         let (token, price) = item?;
@@ -603,27 +4361,35 @@ fn calculate_rewards(
         return Ok(Uint128::zero());
     }
 
-    let reward = portfolio.total_current_value
-        .multiply_ratio(config.reward_rate * time_elapsed as u128, 365 * 24 * 60 * 60 * 100u128);
-    
-    Ok(reward)
+    // Auto-compounding positions have their rewards reinvested by the crank instead.
+    let claimable_value: Uint128 = portfolio.positions
+        .iter()
+        .filter(|p| !p.auto_compound)
+        .map(|p| p.current_value)
+        .sum();
+
+    Ok(accrued_reward(claimable_value, config, time_elapsed))
+}
+
+fn accrued_reward(value: Uint128, config: &Config, time_elapsed: u64) -> Uint128 {
+    value.multiply_ratio(config.reward_rate * Uint128::from(time_elapsed), SECONDS_PER_YEAR * 100)
 }
 
 // Query implementations
-fn query_config(deps: Deps) -> StdResult<Config> {
+fn query_config(deps: Deps<ArchwayQuery>) -> StdResult<Config> {
     CONFIG.load(deps.storage)
 }
 
-fn query_basket(deps: Deps, name: String) -> StdResult<BasketConfig> {
+fn query_basket(deps: Deps<ArchwayQuery>, name: String) -> StdResult<BasketConfig> {
     BASKETS.load(deps.storage, &name)
 }
 
-fn query_portfolio(deps: Deps, address: String) -> StdResult<UserPortfolio> {
+fn query_portfolio(deps: Deps<ArchwayQuery>, address: String) -> StdResult<UserPortfolio> {
     PORTFOLIOS.load(deps.storage, &address)
 }
 
 fn query_investment_history(
-    deps: Deps,
+    deps: Deps<ArchwayQuery>,
     address: String,
     from_timestamp: Option<u64>,
     to_timestamp: Option<u64>,
@@ -632,99 +4398,1456 @@ fn query_investment_history(
     Ok(portfolio.investment_history
         .into_iter()
         .filter(|h| {
-            from_timestamp.map_or(true, |from| h.timestamp >= from) &&
-            to_timestamp.map_or(true, |to| h.timestamp <= to)
+            from_timestamp.is_none_or(|from| h.timestamp >= from) &&
+            to_timestamp.is_none_or(|to| h.timestamp <= to)
         })
         .collect())
 }
 
 fn query_performance_metrics(
-    deps: Deps,
+    deps: Deps<ArchwayQuery>,
     address: String,
     basket_name: Option<String>,
 ) -> StdResult<Vec<(String, i64)>> {
     let portfolio = PORTFOLIOS.load(deps.storage, &address)?;
     let metrics: Vec<(String, i64)> = portfolio.positions
         .into_iter()
-        .filter(|p| basket_name.as_ref().map_or(true, |b| p.basket_name == *b))
+        .filter(|p| basket_name.as_ref().is_none_or(|b| p.basket_name == *b))
         .map(|p| (p.basket_name, p.performance))
         .collect();
     Ok(metrics)
 }
 
-fn query_rewards(deps: Deps, address: String) -> StdResult<Uint128> {
+fn query_rewards(deps: Deps<ArchwayQuery>, address: String) -> StdResult<Uint128> {
     let portfolio = PORTFOLIOS.load(deps.storage, &address)?;
     Ok(portfolio.rewards_earned)
 }
 
+fn query_basket_drift(deps: Deps<ArchwayQuery>, name: String) -> StdResult<BasketDriftResponse> {
+    let basket = BASKETS.load(deps.storage, &name)?;
+    let holdings = BASKET_HOLDINGS.may_load(deps.storage, &name)?.unwrap_or_default();
+    let token_prices = get_token_prices(deps)?;
+
+    let components = component_drift(&holdings, &basket.tokens, &token_prices)?;
+    let max_drift = components.iter().map(|d| d.drift_bps.unsigned_abs()).max().unwrap_or(0);
+
+    Ok(BasketDriftResponse {
+        total_value: holdings_value(&basket.tokens, &holdings, &token_prices)?,
+        needs_rebalance: max_drift > basket.drift_threshold_bps as u32,
+        components,
+    })
+}
+
+fn query_basket_versions(deps: Deps<ArchwayQuery>, basket_name: String) -> StdResult<Vec<BasketVersion>> {
+    BASKET_VERSIONS
+        .prefix(&basket_name)
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_, version)| version))
+        .collect()
+}
+
+/// The basket's composition at `version`, or the one in force at `at_time`.
+fn query_basket_version(
+    deps: Deps<ArchwayQuery>,
+    basket_name: String,
+    version: Option<u32>,
+    at_time: Option<u64>,
+) -> StdResult<BasketVersion> {
+    let found = match (version, at_time) {
+        (Some(version), None) => BASKET_VERSIONS.may_load(deps.storage, (&basket_name, version))?,
+        (None, Some(time)) => BASKET_VERSIONS
+            .prefix(&basket_name)
+            .range(deps.storage, None, None, Order::Descending)
+            .find(|item| item.as_ref().map_or(true, |(_, v)| v.created_at <= time))
+            .transpose()?
+            .map(|(_, version)| version),
+        _ => return Err(StdError::generic_err("Specify exactly one of version or at_time")),
+    };
+    found.ok_or_else(|| StdError::generic_err(format!("No such version of basket {}", basket_name)))
+}
+
+fn query_registered_tokens(deps: Deps<ArchwayQuery>) -> StdResult<Vec<Token>> {
+    TOKEN_REGISTRY
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_, token)| token))
+        .collect()
+}
+
+fn query_dca_schedules(deps: Deps<ArchwayQuery>, owner: String) -> StdResult<Vec<DcaSchedule>> {
+    DCA_SCHEDULES
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_, schedule)| schedule))
+        .filter(|item| item.as_ref().map_or(true, |schedule| schedule.owner == owner))
+        .collect()
+}
+
+fn query_operators(
+    deps: Deps<ArchwayQuery>,
+    env: Env,
+    owner: String,
+) -> StdResult<Vec<OperatorApproval>> {
+    let now = env.block.time.seconds();
+    OPERATORS
+        .prefix(&owner)
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_, approval)| approval))
+        .filter(|item| item.as_ref().map_or(true, |approval| approval.is_active(now)))
+        .collect()
+}
+
+fn query_pending_withdrawals(
+    deps: Deps<ArchwayQuery>,
+    address: Option<String>,
+) -> StdResult<Vec<PendingWithdrawal>> {
+    PENDING_WITHDRAWALS
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_, withdrawal)| withdrawal))
+        .filter(|item| match (item, &address) {
+            (Ok(withdrawal), Some(address)) => withdrawal.user == *address,
+            _ => true,
+        })
+        .collect()
+}
+
+fn query_chain(deps: Deps<ArchwayQuery>, id: String) -> StdResult<ChainInfo> {
+    CHAINS.load(deps.storage, &id)
+}
+
+fn query_chains(deps: Deps<ArchwayQuery>) -> StdResult<Vec<ChainInfo>> {
+    CHAINS
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_, chain)| chain))
+        .collect()
+}
+
+fn query_archway_config(deps: Deps<ArchwayQuery>) -> StdResult<ArchwayConfigResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    Ok(ArchwayConfigResponse {
+        owner_address: config.archway_owner_address,
+        rewards_address: config.archway_rewards_address,
+        flat_fee: config.archway_flat_fee,
+    })
+}
+
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
-    use cosmwasm_std::{coins, from_binary};
-
-    // #[test]
-    // fn proper_initialization() {
-    //     let mut deps = mock_dependencies();
-
-    //     let msg = InstantiateMsg { count: 17 };
-    //     let info = mock_info("creator", &coins(1000, "earth"));
-
-    //     // we can just call .unwrap() to assert this was a success
-    //     let res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
-    //     assert_eq!(0, res.messages.len());
-
-    //     // it worked, let's query the state
-    //     let res = query(deps.as_ref(), mock_env(), QueryMsg::GetCount {}).unwrap();
-    //     let value: GetCountResponse = from_binary(&res).unwrap();
-    //     assert_eq!(17, value.count);
-    // }
-
-    // #[test]
-    // fn increment() {
-    //     let mut deps = mock_dependencies();
-
-    //     let msg = InstantiateMsg { count: 17 };
-    //     let info = mock_info("creator", &coins(2, "token"));
-    //     let _res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
-
-    //     // beneficiary can release it
-    //     let info = mock_info("anyone", &coins(2, "token"));
-    //     let msg = ExecuteMsg::Increment {};
-    //     let _res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
-
-    //     // should increase counter by 1
-    //     let res = query(deps.as_ref(), mock_env(), QueryMsg::GetCount {}).unwrap();
-    //     let value: GetCountResponse = from_binary(&res).unwrap();
-    //     assert_eq!(18, value.count);
-    // }
-
-    // #[test]
-    // fn reset() {
-    //     let mut deps = mock_dependencies();
-
-    //     let msg = InstantiateMsg { count: 17 };
-    //     let info = mock_info("creator", &coins(2, "token"));
-    //     let _res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
-
-    //     // beneficiary can release it
-    //     let unauth_info = mock_info("anyone", &coins(2, "token"));
-    //     let msg = ExecuteMsg::Reset { count: 5 };
-    //     let res = execute(deps.as_mut(), mock_env(), unauth_info, msg);
-    //     match res {
-    //         Err(ContractError::Unauthorized {}) => {}
-    //         _ => panic!("Must return unauthorized error"),
-    //     }
-
-    //     // only the original creator can reset the counter
-    //     let auth_info = mock_info("creator", &coins(2, "token"));
-    //     let msg = ExecuteMsg::Reset { count: 5 };
-    //     let _res = execute(deps.as_mut(), mock_env(), auth_info, msg).unwrap();
-
-    //     // should now be 5
-    //     let res = query(deps.as_ref(), mock_env(), QueryMsg::GetCount {}).unwrap();
-    //     let value: GetCountResponse = from_binary(&res).unwrap();
-    //     assert_eq!(5, value.count);
-    // }
+    use crate::address::ibc_hooks_sender;
+    use crate::ibc::{remote_owner, IBC_APP_VERSION, PACKET_VERSION};
+    use crate::msg::{BasketPacket, BasketPacketMsg, IbcCounterparty};
+    use bech32::ToBase32;
+    use cosmwasm_std::testing::{
+        mock_env, mock_ibc_channel_connect_ack, mock_ibc_channel_open_init, mock_info, MockApi, MockQuerier,
+        MockStorage,
+    };
+    use cosmwasm_std::{coins, from_json, Addr, Attribute, IbcChannel, IbcEndpoint, IbcOrder, OwnedDeps, ReplyOn, SubMsgResponse};
+    use std::marker::PhantomData;
+
+    const ADMIN: &str = "admin";
+
+    fn mock_deps() -> OwnedDeps<MockStorage, MockApi, MockQuerier<ArchwayQuery>, ArchwayQuery> {
+        OwnedDeps {
+            storage: MockStorage::default(),
+            api: MockApi::default(),
+            querier: MockQuerier::new(&[]),
+            custom_query_type: PhantomData,
+        }
+    }
+
+    fn instantiate_msg() -> InstantiateMsg {
+        InstantiateMsg {
+            admin: ADMIN.to_string(),
+            reward_token: "ureward".to_string(),
+            reward_rate: Uint128::new(500),
+            min_lock_period: 0,
+            compound_frequency: 86_400,
+            keeper_tip_bps: 50,
+            treasury: "treasury".to_string(),
+            harvest_treasury_bps: 1_000,
+            dex_router: Some("router".to_string()),
+            max_slippage_bps: 100,
+            ibc_timeout_seconds: 600,
+            oracle: None,
+            price_max_age: 0,
+        }
+    }
+
+    fn setup() -> OwnedDeps<MockStorage, MockApi, MockQuerier<ArchwayQuery>, ArchwayQuery> {
+        let mut deps = mock_deps();
+        instantiate(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), instantiate_msg()).unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADMIN, &[]),
+            ExecuteMsg::SetChain {
+                chain: ChainInfo {
+                    id: "archway".to_string(),
+                    display_name: "Archway".to_string(),
+                    address_format: AddressFormat::Bech32 { prefix: "archway".to_string() },
+                    bridge: None,
+                    enabled: true,
+                },
+            },
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADMIN, &[]),
+            ExecuteMsg::SetChain {
+                chain: ChainInfo {
+                    id: "osmosis".to_string(),
+                    display_name: "Osmosis".to_string(),
+                    address_format: AddressFormat::Bech32 { prefix: "osmo".to_string() },
+                    bridge: None,
+                    enabled: true,
+                },
+            },
+        )
+        .unwrap();
+        deps
+    }
+
+    fn bech32_address(prefix: &str, seed: u8) -> String {
+        bech32::encode(prefix, [seed; 20].to_base32(), bech32::Variant::Bech32).unwrap()
+    }
+
+    fn token(symbol: &str, weight: u8) -> TokenWeight {
+        TokenWeight {
+            token: Token {
+                address: format!("u{}", symbol.to_lowercase()),
+                chain: "archway".to_string(),
+                symbol: symbol.to_string(),
+                decimals: 6,
+                ibc: None,
+            },
+            weight,
+        }
+    }
+
+    fn create_basket_msg(name: &str, tokens: Vec<TokenWeight>) -> ExecuteMsg {
+        ExecuteMsg::CreateBasket {
+            name: name.to_string(),
+            tokens,
+            min_investment: Uint128::new(1),
+            drift_threshold_bps: 500,
+            rebalance_interval: 0,
+            fee_bps: 0,
+        }
+    }
+
+    fn set_prices(deps: DepsMut<ArchwayQuery>, prices: &[(&str, u128)]) {
+        let prices = prices
+            .iter()
+            .map(|(symbol, price)| PriceUpdate {
+                symbol: symbol.to_string(),
+                price: Uint128::new(*price),
+            })
+            .collect();
+        execute(deps, mock_env(), mock_info(ADMIN, &[]), ExecuteMsg::UpdatePrices { prices }).unwrap();
+    }
+
+    fn invest_in_kind(deps: DepsMut<ArchwayQuery>, user: &str, basket: &str, funds: &[Coin], auto_compound: bool) {
+        let msg = ExecuteMsg::Invest {
+            basket_name: basket.to_string(),
+            min_value: Uint128::zero(),
+            auto_compound,
+            in_kind_tolerance_bps: None,
+            zap_in: false,
+        };
+        execute(deps, mock_env(), mock_info(user, funds), msg).unwrap();
+    }
+
+    #[test]
+    fn proper_initialization() {
+        let deps = setup();
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::GetConfig {}).unwrap();
+        let config: Config = from_json(res).unwrap();
+        assert_eq!(ADMIN, config.admin);
+        assert_eq!(1_000, config.harvest_treasury_bps);
+    }
+
+    #[test]
+    fn instantiate_validates_treasury_split() {
+        let mut deps = mock_deps();
+        let msg = InstantiateMsg { harvest_treasury_bps: 10_001, ..instantiate_msg() };
+        instantiate(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap_err();
+
+        let msg = InstantiateMsg { treasury: "Not An Address".to_string(), ..instantiate_msg() };
+        instantiate(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap_err();
+    }
+
+    #[test]
+    fn create_basket_requires_full_weight() {
+        let mut deps = setup();
+        let msg = create_basket_msg("half", vec![token("ARCH", 50)]);
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap_err();
+
+        let msg = create_basket_msg("full", vec![token("ARCH", 60), token("ATOM", 40)]);
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::GetBasket { name: "full".to_string() }).unwrap();
+        let basket: BasketConfig = from_json(res).unwrap();
+        assert_eq!(2, basket.tokens.len());
+    }
+
+    fn update_basket_msg(name: &str, tokens: Vec<TokenWeight>) -> ExecuteMsg {
+        ExecuteMsg::UpdateBasket {
+            name: name.to_string(),
+            tokens,
+            min_investment: None,
+            active: true,
+            drift_threshold_bps: None,
+            rebalance_interval: None,
+            fee_bps: None,
+        }
+    }
+
+    #[test]
+    fn invest_requires_attached_tokens() {
+        let mut deps = setup();
+        let msg = create_basket_msg("core", vec![token("ARCH", 100)]);
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+        set_prices(deps.as_mut(), &[("ARCH", 1_000_000)]);
+
+        let msg = ExecuteMsg::Invest {
+            basket_name: "core".to_string(),
+            min_value: Uint128::new(1_000_000),
+            auto_compound: false,
+            in_kind_tolerance_bps: None,
+            zap_in: false,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg.clone()).unwrap_err();
+        assert!(err.to_string().contains("Attach the tokens to invest"));
+
+        // The minimum is enforced against what was actually deposited.
+        execute(deps.as_mut(), mock_env(), mock_info("alice", &[Coin::new(500_000, "uarch")]), msg).unwrap_err();
+        invest_in_kind(deps.as_mut(), "alice", "core", &[Coin::new(1_000_000, "uarch")], false);
+    }
+
+    #[test]
+    fn update_basket_keeps_held_components() {
+        let mut deps = setup();
+        let msg = create_basket_msg("core", vec![token("ARCH", 50), token("ATOM", 50)]);
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+
+        // Nothing held yet, so components can still be swapped out freely.
+        let msg = update_basket_msg("core", vec![token("ARCH", 50), token("OSMO", 50)]);
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+
+        set_prices(deps.as_mut(), &[("ARCH", 1_000_000), ("OSMO", 1_000_000)]);
+        invest_in_kind(
+            deps.as_mut(),
+            "alice",
+            "core",
+            &[Coin::new(1_000_000, "uarch"), Coin::new(1_000_000, "uosmo")],
+            false,
+        );
+
+        let msg = update_basket_msg("core", vec![token("ARCH", 100)]);
+        let err = execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap_err();
+        assert!(err.to_string().contains("Basket still holds OSMO"));
+
+        let mut changed = token("OSMO", 50);
+        changed.token.decimals = 18;
+        let msg = update_basket_msg("core", vec![token("ARCH", 50), changed]);
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap_err();
+
+        // Weighting it to zero keeps it in the composition until rebalanced away.
+        let msg = update_basket_msg("core", vec![token("ARCH", 100), token("OSMO", 0)]);
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+    }
+
+    #[test]
+    fn removed_component_with_no_holdings_is_ignored() {
+        let mut deps = setup();
+        let msg = create_basket_msg("core", vec![token("ARCH", 50), token("OSMO", 50)]);
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+        set_prices(deps.as_mut(), &[("ARCH", 1_000_000), ("OSMO", 1_000_000)]);
+        invest_in_kind(
+            deps.as_mut(),
+            "alice",
+            "core",
+            &[Coin::new(1_000_000, "uarch"), Coin::new(1_000_000, "uosmo")],
+            false,
+        );
+
+        // As left behind by a rebalance that sold all of OSMO into ARCH.
+        let holdings = HashMap::from([
+            ("ARCH".to_string(), Uint128::new(2_000_000)),
+            ("OSMO".to_string(), Uint128::zero()),
+        ]);
+        BASKET_HOLDINGS.save(deps.as_mut().storage, "core", &holdings).unwrap();
+
+        let msg = update_basket_msg("core", vec![token("ARCH", 100)]);
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+        query(deps.as_ref(), mock_env(), QueryMsg::GetBasketDrift { name: "core".to_string() }).unwrap();
+        invest_in_kind(deps.as_mut(), "bob", "core", &[Coin::new(1_000_000, "uarch")], false);
+    }
+
+    #[test]
+    fn basket_creation_without_bond_policy_is_admin_only() {
+        let mut deps = setup();
+        let msg = create_basket_msg("mine", vec![token("ARCH", 100)]);
+        let info = mock_info("anyone", &coins(1_000, "uarch"));
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+    }
+
+    #[test]
+    fn compound_swaps_funded_rewards_into_components() {
+        let mut deps = setup();
+        let msg = create_basket_msg("core", vec![token("ARCH", 100)]);
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+        set_prices(deps.as_mut(), &[("ARCH", 2_000_000), ("RWD", 1_000_000)]);
+        invest_in_kind(deps.as_mut(), "alice", "core", &coins(1_000_000_000, "uarch"), true);
+
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(365 * 24 * 60 * 60);
+        let compound = ExecuteMsg::Compound { basket_name: "core".to_string(), limit: None };
+
+        // Nothing backs the rewards yet.
+        let err = execute(deps.as_mut(), env.clone(), mock_info("keeper", &[]), compound.clone()).unwrap_err();
+        assert!(err.to_string().contains("Reward pool cannot fund compounding"));
+
+        let fund = ExecuteMsg::FundRewards {};
+        let err = execute(deps.as_mut(), env.clone(), mock_info(ADMIN, &coins(1, "uarch")), fund.clone()).unwrap_err();
+        assert!(err.to_string().contains("Send only ureward"));
+        execute(deps.as_mut(), env.clone(), mock_info(ADMIN, &coins(1_000_000_000_000, "ureward")), fund).unwrap();
+        let err = execute(deps.as_mut(), env.clone(), mock_info("keeper", &[]), compound.clone()).unwrap_err();
+        assert!(err.to_string().contains("Reward token has no oracle price"));
+
+        let mut reward = token("RWD", 0).token;
+        reward.address = "ureward".to_string();
+        execute(deps.as_mut(), env.clone(), mock_info(ADMIN, &[]), ExecuteMsg::RegisterToken { token: reward })
+            .unwrap();
+        let res = execute(deps.as_mut(), env.clone(), mock_info("keeper", &[]), compound).unwrap();
+
+        // The pool pays for the reinvested rewards and the tip.
+        let pool: Uint128 = from_json(query(deps.as_ref(), env, QueryMsg::GetRewardPool {}).unwrap()).unwrap();
+        assert_eq!(Uint128::new(1_000_000_000_000 - 20_000_000_000), pool);
+
+        // The keeper tip is paid, and the rest is swapped with an oracle floor.
+        assert_eq!(2, res.messages.len());
+        let CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, funds, msg, .. }) = &res.messages[1].msg else {
+            panic!("expected a router swap");
+        };
+        assert_eq!("router", contract_addr);
+        assert_eq!("ureward", funds[0].denom);
+        let RouterExecuteMsg::Swap { minimum_receive, .. } = from_json(msg).unwrap();
+        assert!(!minimum_receive.is_zero());
+    }
+
+    #[test]
+    fn compound_credits_reward_component_without_swaps() {
+        let mut deps = setup();
+        let mut reward = token("RWD", 100);
+        reward.token.address = "ureward".to_string();
+        let msg = create_basket_msg("rewards", vec![reward]);
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+        set_prices(deps.as_mut(), &[("RWD", 1_000_000)]);
+        invest_in_kind(deps.as_mut(), "alice", "rewards", &coins(1_000_000_000, "ureward"), true);
+
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(365 * 24 * 60 * 60);
+        let funds = coins(1_000_000_000_000, "ureward");
+        execute(deps.as_mut(), env.clone(), mock_info(ADMIN, &funds), ExecuteMsg::FundRewards {}).unwrap();
+        let compound = ExecuteMsg::Compound { basket_name: "rewards".to_string(), limit: None };
+        execute(deps.as_mut(), env, mock_info("keeper", &[]), compound).unwrap();
+
+        // A year at the 500% reward rate, less the 0.5% keeper tip, lands in
+        // the pool and the position.
+        let holdings = BASKET_HOLDINGS.load(&deps.storage, "rewards").unwrap();
+        assert_eq!(Uint128::new(5_975_000_000), holdings["RWD"]);
+        let portfolio = PORTFOLIOS.load(&deps.storage, "alice").unwrap();
+        assert_eq!(Uint128::new(5_975_000_000), portfolio.positions[0].shares);
+    }
+
+    #[test]
+    fn rebalance_runs_on_drift_or_schedule_and_books_the_swap() {
+        let mut deps = setup();
+        let msg = ExecuteMsg::CreateBasket {
+            name: "core".to_string(),
+            tokens: vec![token("ARCH", 50), token("ATOM", 50)],
+            min_investment: Uint128::new(1),
+            drift_threshold_bps: 500,
+            rebalance_interval: 3_600,
+            fee_bps: 0,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+        set_prices(deps.as_mut(), &[("ARCH", 2_000_000), ("ATOM", 10_000_000)]);
+        let funds = [Coin::new(1_000_000, "uarch"), Coin::new(200_000, "uatom")];
+        invest_in_kind(deps.as_mut(), "alice", "core", &funds, false);
+
+        let env = mock_env();
+        let rebalance = ExecuteMsg::Rebalance { basket_name: "core".to_string() };
+        let drift = |deps: &OwnedDeps<MockStorage, MockApi, MockQuerier<ArchwayQuery>, ArchwayQuery>| -> BasketDriftResponse {
+            from_json(query(deps.as_ref(), env.clone(), QueryMsg::GetBasketDrift { name: "core".to_string() }).unwrap())
+                .unwrap()
+        };
+
+        // A 4.5% drift stays inside the 5% band.
+        set_prices(deps.as_mut(), &[("ATOM", 12_000_000)]);
+        let response = drift(&deps);
+        assert_eq!((454, false), (response.components[1].drift_bps, response.needs_rebalance));
+        let err = execute(deps.as_mut(), env.clone(), mock_info("keeper", &[]), rebalance.clone()).unwrap_err();
+        assert!(err.to_string().contains("Basket is within its drift band"));
+
+        // Past the band, the surplus ATOM is sold for ARCH with a slippage floor.
+        set_prices(deps.as_mut(), &[("ATOM", 14_000_000)]);
+        let response = drift(&deps);
+        assert_eq!(Uint128::new(4_800_000), response.total_value);
+        assert_eq!((833, true), (response.components[1].drift_bps, response.needs_rebalance));
+        let res = execute(deps.as_mut(), env.clone(), mock_info("keeper", &[]), rebalance.clone()).unwrap();
+        let CosmosMsg::Wasm(WasmMsg::Execute { funds, msg, .. }) = &res.messages[0].msg else {
+            panic!("expected a router swap");
+        };
+        assert_eq!(coins(28_571, "uatom"), *funds);
+        let RouterExecuteMsg::Swap { ask_denom, minimum_receive } = from_json(msg).unwrap();
+        assert_eq!(("uarch".to_string(), Uint128::new(198_000)), (ask_denom, minimum_receive));
+
+        // The reply books what the router actually returned.
+        deps.querier.update_balance(env.contract.address.clone(), coins(199_000, "uarch"));
+        let ok = Reply { id: SWAP_REPLY_ID, result: SubMsgResult::Ok(SubMsgResponse { events: vec![], data: None }) };
+        reply(deps.as_mut(), env.clone(), ok).unwrap();
+        let holdings = BASKET_HOLDINGS.load(&deps.storage, "core").unwrap();
+        assert_eq!(Uint128::new(1_199_000), holdings["ARCH"]);
+        assert_eq!(Uint128::new(200_000 - 28_571), holdings["ATOM"]);
+        assert!(!drift(&deps).needs_rebalance);
+
+        // Inside the band, only the elapsed interval allows another run.
+        let mut later = env.clone();
+        later.block.time = later.block.time.plus_seconds(1_800);
+        let err = execute(deps.as_mut(), later.clone(), mock_info("keeper", &[]), rebalance.clone()).unwrap_err();
+        assert!(err.to_string().contains("Basket is within its drift band"));
+        later.block.time = later.block.time.plus_seconds(1_800);
+        execute(deps.as_mut(), later.clone(), mock_info("keeper", &[]), rebalance).unwrap();
+        assert_eq!(later.block.time.seconds(), BASKETS.load(&deps.storage, "core").unwrap().last_rebalanced);
+    }
+
+    fn harvested(deps: DepsMut<ArchwayQuery>, total_rewards: Vec<Coin>) -> Response<ArchwayMsg> {
+        let data = to_json_binary(&WithdrawRewardsResponse { records_num: 1, total_rewards }).unwrap();
+        let result = SubMsgResult::Ok(SubMsgResponse { events: vec![], data: Some(data) });
+        reply(deps, mock_env(), Reply { id: HARVEST_REPLY_ID, result }).unwrap()
+    }
+
+    fn claim_harvest(deps: DepsMut<ArchwayQuery>, user: &str) -> Vec<Coin> {
+        let res = execute(deps, mock_env(), mock_info(user, &[]), ExecuteMsg::ClaimRewards { owner: None }).unwrap();
+        match &res.messages[..] {
+            [sub] => match &sub.msg {
+                CosmosMsg::Bank(BankMsg::Send { to_address, amount }) if to_address == user => amount.clone(),
+                msg => panic!("unexpected message {:?}", msg),
+            },
+            messages => panic!("expected one payout, got {:?}", messages),
+        }
+    }
+
+    #[test]
+    fn harvest_splits_treasury_and_baskets_pro_rata() {
+        let mut deps = setup();
+        for (name, symbol) in [("core", "ARCH"), ("alt", "ATOM")] {
+            let msg = create_basket_msg(name, vec![token(symbol, 100)]);
+            execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+        }
+        set_prices(deps.as_mut(), &[("ARCH", 2_000_000), ("ATOM", 10_000_000)]);
+
+        // Without holders, everything goes to the treasury.
+        let res = harvested(deps.as_mut(), coins(1_000, "aarch"));
+        assert_eq!(
+            CosmosMsg::Bank(BankMsg::Send { to_address: "treasury".to_string(), amount: coins(1_000, "aarch") }),
+            res.messages[0].msg
+        );
+
+        invest_in_kind(deps.as_mut(), "alice", "core", &coins(1_000_000, "uarch"), false);
+        invest_in_kind(deps.as_mut(), "bob", "alt", &coins(100_000, "uatom"), false);
+        invest_in_kind(deps.as_mut(), "carol", "alt", &coins(300_000, "uatom"), false);
+
+        // 10% to the treasury; the rest follows each basket's locked value
+        // (2 vs 4) and then each holder's shares.
+        let res = harvested(deps.as_mut(), coins(900_000, "aarch"));
+        assert_eq!(
+            CosmosMsg::Bank(BankMsg::Send { to_address: "treasury".to_string(), amount: coins(90_000, "aarch") }),
+            res.messages[0].msg
+        );
+        invest_in_kind(deps.as_mut(), "dave", "core", &coins(1_000_000, "uarch"), false);
+        assert_eq!(coins(270_000, "aarch"), claim_harvest(deps.as_mut(), "alice"));
+        assert_eq!(coins(135_000, "aarch"), claim_harvest(deps.as_mut(), "bob"));
+        assert_eq!(coins(405_000, "aarch"), claim_harvest(deps.as_mut(), "carol"));
+
+        // Later holders only share in later harvests.
+        let err = execute(deps.as_mut(), mock_env(), mock_info("dave", &[]), ExecuteMsg::ClaimRewards { owner: None })
+            .unwrap_err();
+        assert!(err.to_string().contains("No rewards to claim"));
+        harvested(deps.as_mut(), coins(1_000_000, "aarch"));
+        assert_eq!(coins(225_000, "aarch"), claim_harvest(deps.as_mut(), "alice"));
+        assert_eq!(coins(225_000, "aarch"), claim_harvest(deps.as_mut(), "dave"));
+    }
+
+    #[test]
+    fn dca_runs_deposit_in_kind_or_zap_in() {
+        let mut deps = setup();
+        let msg = create_basket_msg("core", vec![token("ARCH", 100)]);
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), ExecuteMsg::RegisterToken { token: token("USDC", 0).token })
+            .unwrap();
+        set_prices(deps.as_mut(), &[("ARCH", 2_000_000), ("USDC", 1_000_000)]);
+
+        let create = || ExecuteMsg::CreateDca {
+            basket_name: "core".to_string(),
+            amount_per_run: Uint128::new(1_000_000),
+            interval: 60,
+            end: DcaEnd::AfterRuns { runs: 2 },
+            auto_compound: false,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("alice", &coins(2_000_000, "uosmo")), create())
+            .unwrap_err();
+        assert!(err.to_string().contains("uosmo is not a registered token"));
+        for denom in ["uarch", "uusdc", "uusdc"] {
+            execute(deps.as_mut(), mock_env(), mock_info("alice", &coins(2_000_000, denom)), create()).unwrap();
+        }
+
+        let env = mock_env();
+        let res = execute(deps.as_mut(), env.clone(), mock_info("keeper", &[]), ExecuteMsg::RunDca { limit: None })
+            .unwrap();
+
+        // Every run goes out as its own self-call, already rescheduled.
+        assert!(res.attributes.contains(&Attribute::new("dispatched", "3")));
+        let runs: Vec<ExecuteMsg> = res
+            .messages
+            .iter()
+            .map(|sub| {
+                assert_eq!((DCA_RUN_REPLY_ID, ReplyOn::Always), (sub.id, sub.reply_on.clone()));
+                let CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, msg, .. }) = &sub.msg else {
+                    panic!("expected a DCA run");
+                };
+                assert_eq!(env.contract.address.as_str(), contract_addr);
+                from_json(msg).unwrap()
+            })
+            .collect();
+        for id in 1..=3 {
+            assert!(DCA_QUEUE.has(&deps.storage, (env.block.time.seconds() + 60, id)));
+        }
+        execute(deps.as_mut(), env.clone(), mock_info("keeper", &[]), runs[0].clone()).unwrap_err();
+
+        // The component run lands in the pool as-is, less the keeper tip.
+        let contract = mock_info(env.contract.address.as_str(), &[]);
+        let res = execute(deps.as_mut(), env.clone(), contract.clone(), runs[0].clone()).unwrap();
+        assert!(res.messages.iter().any(|sub| sub.msg
+            == CosmosMsg::Bank(BankMsg::Send { to_address: "keeper".to_string(), amount: coins(5_000, "uarch") })));
+        let holdings = BASKET_HOLDINGS.load(&deps.storage, "core").unwrap();
+        assert_eq!(Uint128::new(995_000), holdings["ARCH"]);
+        let portfolio = PORTFOLIOS.load(&deps.storage, "alice").unwrap();
+        assert_eq!(Uint128::new(1_990_000), portfolio.positions[0].initial_investment);
+        assert_eq!(Uint128::new(1_000_000), DCA_SCHEDULES.load(&deps.storage, 1).unwrap().escrow.amount);
+
+        // A USDC run is zapped in.
+        let res = execute(deps.as_mut(), env.clone(), contract, runs[1].clone()).unwrap();
+        let swap = &res.messages[0];
+        assert_eq!(SWAP_REPLY_ID, swap.id);
+        let CosmosMsg::Wasm(WasmMsg::Execute { funds, .. }) = &swap.msg else {
+            panic!("expected a router swap");
+        };
+        assert_eq!(coins(995_000, "uusdc"), *funds);
+
+        // A run that fails is skipped: its escrow stays and its next run stands.
+        let ok = |id| Reply { id, result: SubMsgResult::Ok(SubMsgResponse { events: vec![], data: None }) };
+        reply(deps.as_mut(), env.clone(), ok(DCA_RUN_REPLY_ID)).unwrap();
+        reply(deps.as_mut(), env.clone(), ok(DCA_RUN_REPLY_ID)).unwrap();
+        let failed = Reply {
+            id: DCA_RUN_REPLY_ID,
+            result: SubMsgResult::Err("Swap into ARCH returned 0, below minimum 1".to_string()),
+        };
+        let res = reply(deps.as_mut(), env.clone(), failed).unwrap();
+        assert!(res.attributes.contains(&Attribute::new("action", "dca_run_skipped")));
+        assert!(res.attributes.contains(&Attribute::new("id", "3")));
+        let schedule = DCA_SCHEDULES.load(&deps.storage, 3).unwrap();
+        assert_eq!((Uint128::new(2_000_000), 0), (schedule.escrow.amount, schedule.runs));
+        assert!(DCA_QUEUE.has(&deps.storage, (env.block.time.seconds() + 60, 3)));
+        assert!(DCA_RUN_QUEUE.may_load(&deps.storage).unwrap().is_none());
+    }
+
+    #[test]
+    fn operators_act_within_scope_until_expiry_or_revocation() {
+        let mut deps = setup();
+        let msg = create_basket_msg("core", vec![token("ARCH", 100)]);
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+        set_prices(deps.as_mut(), &[("ARCH", 2_000_000)]);
+        invest_in_kind(deps.as_mut(), "alice", "core", &coins(1_000_000, "uarch"), false);
+
+        let env = mock_env();
+        let now = env.block.time.seconds();
+        let approve = |permissions, expires| ExecuteMsg::ApproveOperator {
+            operator: "bob".to_string(),
+            permissions,
+            expires,
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), approve(vec![OperatorPermission::Compound], Some(now)))
+            .unwrap_err();
+        assert!(err.to_string().contains("Approval is already expired"));
+        execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), approve(vec![OperatorPermission::Compound], Some(now + 100)))
+            .unwrap();
+
+        // bob may toggle compounding for alice but not claim for alice, and
+        // nobody else may act for alice at all.
+        let compound = |enabled| ExecuteMsg::SetAutoCompound {
+            basket_name: "core".to_string(),
+            enabled,
+            owner: Some("alice".to_string()),
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("bob", &[]), compound(true)).unwrap();
+        assert!(PORTFOLIOS.load(&deps.storage, "alice").unwrap().positions[0].auto_compound);
+        let claim = ExecuteMsg::ClaimRewards { owner: Some("alice".to_string()) };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("bob", &[]), claim).unwrap_err();
+        assert!(err.to_string().contains("Unauthorized"));
+        execute(deps.as_mut(), env.clone(), mock_info("carol", &[]), compound(false)).unwrap_err();
+
+        // The approval lapses at its expiry.
+        let mut expired = env.clone();
+        expired.block.time = expired.block.time.plus_seconds(100);
+        let err = execute(deps.as_mut(), expired, mock_info("bob", &[]), compound(false)).unwrap_err();
+        assert!(err.to_string().contains("Unauthorized"));
+
+        // A revoked approval grants nothing, and cannot be revoked twice.
+        execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), approve(vec![OperatorPermission::Compound], None))
+            .unwrap();
+        let revoke = ExecuteMsg::RevokeOperator { operator: "bob".to_string() };
+        execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), revoke.clone()).unwrap();
+        let err = execute(deps.as_mut(), env.clone(), mock_info("bob", &[]), compound(false)).unwrap_err();
+        assert!(err.to_string().contains("Unauthorized"));
+        let err = execute(deps.as_mut(), env, mock_info("alice", &[]), revoke).unwrap_err();
+        assert!(err.to_string().contains("Operator not approved"));
+    }
+
+    fn connect_basket_channel(deps: DepsMut<ArchwayQuery>, channel_id: &str) -> StdResult<()> {
+        let msg = mock_ibc_channel_connect_ack(channel_id, IbcOrder::Unordered, IBC_APP_VERSION);
+        crate::ibc::ibc_channel_connect(deps, mock_env(), msg).map(|_| ())
+    }
+
+    #[test]
+    fn basket_channels_require_allowed_counterparty() {
+        let mut deps = setup();
+        let open = mock_ibc_channel_open_init("channel-9", IbcOrder::Unordered, IBC_APP_VERSION);
+        let err = crate::ibc::ibc_channel_open(deps.as_mut(), mock_env(), open.clone()).unwrap_err();
+        assert!(err.to_string().contains("their_port on connection-2 is not allowed"));
+        connect_basket_channel(deps.as_mut(), "channel-9").unwrap_err();
+
+        let counterparty = IbcCounterparty {
+            port_id: "their_port".to_string(),
+            connection_id: "connection-2".to_string(),
+        };
+        let allow = ExecuteMsg::SetIbcCounterparty { counterparty, allowed: true };
+        execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), allow.clone()).unwrap_err();
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), allow).unwrap();
+
+        crate::ibc::ibc_channel_open(deps.as_mut(), mock_env(), open).unwrap();
+        connect_basket_channel(deps.as_mut(), "channel-9").unwrap();
+    }
+
+    #[test]
+    fn ibc_invest_credits_only_the_hooks_sender() {
+        let mut deps = setup();
+        let msg = create_basket_msg("core", vec![osmo_over_ibc(100)]);
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+        set_prices(deps.as_mut(), &[("OSMO", 1_000_000)]);
+        let counterparty = IbcCounterparty {
+            port_id: "their_port".to_string(),
+            connection_id: "connection-2".to_string(),
+        };
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADMIN, &[]),
+            ExecuteMsg::SetIbcCounterparty { counterparty, allowed: true },
+        )
+        .unwrap();
+        connect_basket_channel(deps.as_mut(), "channel-9").unwrap();
+
+        let mut env = mock_env();
+        env.contract.address = Addr::unchecked(bech32_address("archway", 7));
+        let remote = bech32_address("osmo", 1);
+        let invest = ExecuteMsg::IbcInvest {
+            channel_id: "channel-9".to_string(),
+            sender: remote.clone(),
+            basket_name: "core".to_string(),
+            min_value: Uint128::zero(),
+            auto_compound: false,
+        };
+        let funds = coins(1_000_000, "uosmo");
+
+        // Anyone else claiming to act for the remote sender is refused.
+        let err = execute(deps.as_mut(), env.clone(), mock_info("alice", &funds), invest.clone()).unwrap_err();
+        assert!(err.to_string().contains("Unauthorized"));
+
+        let hooks = ibc_hooks_sender("channel-0", &remote, "archway").unwrap();
+        execute(deps.as_mut(), env.clone(), mock_info(&hooks, &[]), invest.clone()).unwrap_err();
+
+        // Funds must arrive over the basket channel's own connection.
+        let transfer_channel = |connection_id: &str| {
+            IbcChannel::new(
+                IbcEndpoint { port_id: "transfer".to_string(), channel_id: "channel-0".to_string() },
+                IbcEndpoint { port_id: "transfer".to_string(), channel_id: "channel-141".to_string() },
+                IbcOrder::Unordered,
+                "ics20-1",
+                connection_id,
+            )
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info(&hooks, &funds), invest.clone()).unwrap_err();
+        assert!(err.to_string().contains("Unknown transfer channel channel-0"));
+        deps.querier.update_ibc("transfer", &[transfer_channel("connection-3")]);
+        let err = execute(deps.as_mut(), env.clone(), mock_info(&hooks, &funds), invest.clone()).unwrap_err();
+        assert!(err.to_string().contains("Transfer channel channel-0 is not on channel-9's connection connection-2"));
+        deps.querier.update_ibc("transfer", &[transfer_channel("connection-2")]);
+        execute(deps.as_mut(), env, mock_info(&hooks, &funds), invest).unwrap();
+
+        let portfolio = PORTFOLIOS.load(&deps.storage, &remote_owner("channel-9", &remote)).unwrap();
+        assert_eq!(Uint128::new(1_000_000), portfolio.positions[0].shares);
+        assert_eq!(Uint128::new(1_000_000), BASKET_HOLDINGS.load(&deps.storage, "core").unwrap()["OSMO"]);
+
+        // A withdraw packet is refused up front when its sender is no address
+        // on the chain OSMO is routed to.
+        let packet = BasketPacket {
+            version: PACKET_VERSION,
+            msg: BasketPacketMsg::Withdraw {
+                sender: bech32_address("cosmos", 1),
+                basket_name: "core".to_string(),
+                shares: Uint128::new(1),
+            },
+        };
+        let process = ExecuteMsg::ProcessIbcPacket { channel_id: "channel-9".to_string(), packet };
+        let contract = mock_env().contract.address;
+        let err = execute(deps.as_mut(), mock_env(), mock_info(contract.as_str(), &[]), process).unwrap_err();
+        assert!(err.to_string().contains("must use the osmo prefix"));
+        assert_eq!(Uint128::new(1_000_000), BASKET_HOLDINGS.load(&deps.storage, "core").unwrap()["OSMO"]);
+    }
+
+    fn osmo_over_ibc(weight: u8) -> TokenWeight {
+        let mut osmo = token("OSMO", weight);
+        osmo.token.chain = "osmosis".to_string();
+        osmo.token.ibc = Some(IbcRoute {
+            channel_id: "channel-0".to_string(),
+            denom_trace: "transfer/channel-0/uosmo".to_string(),
+        });
+        osmo
+    }
+
+    fn withdraw_all_to(basket: &str, remote_recipient: &str) -> ExecuteMsg {
+        ExecuteMsg::Withdraw {
+            basket_name: basket.to_string(),
+            percentage: 100,
+            remote_recipient: Some(remote_recipient.to_string()),
+            destinations: vec![],
+            zap_out: None,
+            owner: None,
+        }
+    }
+
+    #[test]
+    fn remote_withdrawals_use_tracked_ibc_transfers() {
+        let mut deps = setup();
+        let msg = create_basket_msg("core", vec![osmo_over_ibc(100)]);
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+        let msg = create_basket_msg("mixed", vec![osmo_over_ibc(50), token("ARCH", 50)]);
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+        set_prices(deps.as_mut(), &[("OSMO", 1_000_000), ("ARCH", 1_000_000)]);
+        invest_in_kind(deps.as_mut(), "alice", "core", &coins(1_000_000, "uosmo"), false);
+        invest_in_kind(
+            deps.as_mut(),
+            "alice",
+            "mixed",
+            &[Coin::new(1_000_000, "uosmo"), Coin::new(1_000_000, "uarch")],
+            false,
+        );
+
+        let env = mock_env();
+        let alice_osmo = bech32_address("osmo", 2);
+        let res = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), withdraw_all_to("core", &alice_osmo))
+            .unwrap();
+        let CosmosMsg::Stargate { type_url, value } = &res.messages[0].msg else {
+            panic!("expected a Stargate transfer");
+        };
+        assert_eq!("/ibc.applications.transfer.v1.MsgTransfer", type_url);
+        let transfer = MsgTransfer::decode(value.as_slice()).unwrap();
+        assert_eq!("channel-0", transfer.source_channel);
+        assert_eq!(alice_osmo, transfer.receiver);
+        assert_eq!(format!("{{\"ibc_callback\":\"{}\"}}", env.contract.address), transfer.memo);
+
+        // ARCH has no way to reach the remote recipient.
+        let err = execute(deps.as_mut(), env, mock_info("alice", &[]), withdraw_all_to("mixed", &alice_osmo))
+            .unwrap_err();
+        assert!(err.to_string().contains("ARCH cannot be sent to a remote chain"));
+    }
+
+    #[test]
+    fn payout_routes_are_checked_before_redeeming() {
+        let mut deps = setup();
+        let chain = ChainInfo {
+            id: "polygon".to_string(),
+            display_name: "Polygon".to_string(),
+            address_format: AddressFormat::EvmHex,
+            bridge: Some(BridgeConfig {
+                contract: "wormhole".to_string(),
+                schema: BridgeMsgSchema::Wormhole { recipient_chain: 5 },
+                fee_denom: "uarch".to_string(),
+                fee_amount: Uint128::zero(),
+            }),
+            enabled: true,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), ExecuteMsg::SetChain { chain }).unwrap();
+        let mut weth = token("WETH", 0);
+        weth.token.chain = "polygon".to_string();
+        let msg = create_basket_msg("core", vec![osmo_over_ibc(100), weth]);
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+        set_prices(deps.as_mut(), &[("OSMO", 1_000_000), ("WETH", 1_000_000)]);
+        invest_in_kind(deps.as_mut(), "alice", "core", &coins(1_000_000, "uosmo"), false);
+
+        // An archway address is not valid on Osmosis, where OSMO is routed.
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("alice", &[]),
+            withdraw_all_to("core", &bech32_address("archway", 3)),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("must use the osmo prefix"));
+        let portfolio = PORTFOLIOS.load(&deps.storage, "alice").unwrap();
+        assert_eq!(Uint128::new(1_000_000), portfolio.positions[0].shares);
+
+        // Nothing is paid out on Polygon, so it needs no destination.
+        let msg = withdraw_all_to("core", &bech32_address("osmo", 3));
+        execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap();
+    }
+
+    fn bridge(schema: BridgeMsgSchema, fee_amount: u128) -> BridgeConfig {
+        BridgeConfig {
+            contract: "bridge".to_string(),
+            schema,
+            fee_denom: "uarch".to_string(),
+            fee_amount: Uint128::new(fee_amount),
+        }
+    }
+
+    fn bridge_call(msg: &CosmosMsg<ArchwayMsg>) -> (BridgeExecuteMsg, Vec<Coin>) {
+        let CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, msg, funds }) = msg else {
+            panic!("expected a bridge call");
+        };
+        assert_eq!("bridge", contract_addr);
+        (from_json(msg).unwrap(), funds.clone())
+    }
+
+    #[test]
+    fn generic_and_hyperlane_bridges_attach_tokens_and_fee() {
+        let schema = BridgeMsgSchema::Generic { destination_chain: "axelar".to_string() };
+        let msgs = bridge_transfer_msgs(&bridge(schema, 50), Coin::new(1_000, "uusdc"), "0xabc").unwrap();
+        assert_eq!(1, msgs.len());
+        let (msg, funds) = bridge_call(&msgs[0]);
+        let expected = BridgeExecuteMsg::Bridge {
+            destination_chain: "axelar".to_string(),
+            recipient: "0xabc".to_string(),
+        };
+        assert_eq!(expected, msg);
+        assert_eq!(vec![Coin::new(50, "uarch"), Coin::new(1_000, "uusdc")], funds);
+
+        // A fee in the bridged denom is added to the same coin.
+        let schema = BridgeMsgSchema::HyperlaneWarp { dest_domain: 137 };
+        let recipient = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+        let mut config = bridge(schema, 50);
+        config.fee_denom = "uusdc".to_string();
+        let msgs = bridge_transfer_msgs(&config, Coin::new(1_000, "uusdc"), recipient).unwrap();
+        let (msg, funds) = bridge_call(&msgs[0]);
+        let BridgeExecuteMsg::TransferRemote { dest_domain, recipient: padded, amount } = msg else {
+            panic!("expected transfer_remote");
+        };
+        assert_eq!((137, Uint128::new(1_000)), (dest_domain, amount));
+        assert_eq!(padded_recipient(recipient).unwrap(), padded);
+        assert_eq!(coins(1_050, "uusdc"), funds);
+
+        let msgs = bridge_transfer_msgs(&bridge(BridgeMsgSchema::HyperlaneWarp { dest_domain: 1 }, 0), Coin::new(1, "uusdc"), "0x01")
+            .unwrap();
+        assert_eq!(coins(1, "uusdc"), bridge_call(&msgs[0]).1);
+    }
+
+    #[test]
+    fn padded_recipient_left_pads_to_32_bytes() {
+        let padded = padded_recipient("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed").unwrap();
+        assert_eq!(32, padded.len());
+        assert_eq!([0u8; 12], padded.as_slice()[..12]);
+        assert_eq!("5aaeb6053f3e94c9b9a09f33669435e7ef1beaed", HexBinary::from(&padded.as_slice()[12..]).to_hex());
+        // Unprefixed and full-width recipients pass through unchanged.
+        let full = "ab".repeat(32);
+        assert_eq!(full, padded_recipient(&full).unwrap().to_hex());
+
+        let err = padded_recipient(&format!("0x{}", "ab".repeat(33))).unwrap_err();
+        assert!(err.to_string().contains("Recipient longer than 32 bytes"));
+        padded_recipient("0xnothex").unwrap_err();
+    }
+
+    #[test]
+    fn withdrawals_take_exactly_the_bridge_fees() {
+        let mut deps = setup();
+        let chain = ChainInfo {
+            id: "polygon".to_string(),
+            display_name: "Polygon".to_string(),
+            address_format: AddressFormat::EvmHex,
+            bridge: Some(bridge(BridgeMsgSchema::Generic { destination_chain: "polygon".to_string() }, 100)),
+            enabled: true,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), ExecuteMsg::SetChain { chain }).unwrap();
+        let mut weth = token("WETH", 100);
+        weth.token.chain = "polygon".to_string();
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), create_basket_msg("evm", vec![weth])).unwrap();
+        set_prices(deps.as_mut(), &[("WETH", 1_000_000)]);
+        invest_in_kind(deps.as_mut(), "alice", "evm", &coins(1_000_000, "uweth"), false);
+
+        let destinations = vec![ChainDestination {
+            chain: "polygon".to_string(),
+            address: format!("0x{}", "ab".repeat(20)),
+        }];
+        let withdraw = ExecuteMsg::Withdraw {
+            basket_name: "evm".to_string(),
+            percentage: 10,
+            remote_recipient: None,
+            destinations: destinations.clone(),
+            zap_out: None,
+            owner: None,
+        };
+        let attempt = |deps: DepsMut<ArchwayQuery>, funds: &[Coin]| {
+            execute(deps, mock_env(), mock_info("alice", funds), withdraw.clone())
+        };
+        let err = attempt(deps.as_mut(), &coins(99, "uarch")).unwrap_err();
+        assert!(err.to_string().contains("Insufficient bridge fee: need 100uarch, sent 99uarch"));
+        let err = attempt(deps.as_mut(), &coins(150, "uarch")).unwrap_err();
+        assert!(err.to_string().contains("Sent 150uarch but only 100uarch is needed for bridge fees"));
+        let err = attempt(deps.as_mut(), &[Coin::new(100, "uarch"), Coin::new(5, "uosmo")]).unwrap_err();
+        assert!(err.to_string().contains("Sent 5uosmo but only 0uosmo is needed for bridge fees"));
+        attempt(deps.as_mut(), &coins(100, "uarch")).unwrap();
+
+        // Keepers triggering an exit are held to the same rule.
+        invest_in_kind(deps.as_mut(), "bob", "evm", &coins(1_000_000, "uweth"), false);
+        let triggers = ExecuteMsg::SetExitTriggers {
+            basket_name: "evm".to_string(),
+            stop_loss: Some(ExitThreshold::Percent { bps: 2_000 }),
+            take_profit: None,
+            destinations,
+        };
+        for user in ["alice", "bob"] {
+            execute(deps.as_mut(), mock_env(), mock_info(user, &[]), triggers.clone()).unwrap();
+        }
+        set_prices(deps.as_mut(), &[("WETH", 500_000)]);
+        let exit = |owner: &str| ExecuteMsg::TriggerExit { owner: owner.to_string(), basket_name: "evm".to_string() };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("keeper", &coins(1_000, "uarch")), exit("bob")).unwrap_err();
+        assert!(err.to_string().contains("Sent 1000uarch but only 100uarch is needed for bridge fees"));
+        execute(deps.as_mut(), mock_env(), mock_info("keeper", &coins(100, "uarch")), exit("alice")).unwrap();
+    }
+
+    #[test]
+    fn wormhole_leg_is_sent_and_rolled_back_as_a_whole() {
+        let mut deps = setup();
+        let chain = ChainInfo {
+            id: "polygon".to_string(),
+            display_name: "Polygon".to_string(),
+            address_format: AddressFormat::EvmHex,
+            bridge: Some(BridgeConfig {
+                contract: "wormhole".to_string(),
+                schema: BridgeMsgSchema::Wormhole { recipient_chain: 5 },
+                fee_denom: "uarch".to_string(),
+                fee_amount: Uint128::zero(),
+            }),
+            enabled: true,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), ExecuteMsg::SetChain { chain }).unwrap();
+        let mut weth = token("WETH", 100);
+        weth.token.chain = "polygon".to_string();
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), create_basket_msg("evm", vec![weth])).unwrap();
+        set_prices(deps.as_mut(), &[("WETH", 1_000_000)]);
+        invest_in_kind(deps.as_mut(), "alice", "evm", &coins(1_000_000, "uweth"), false);
+
+        let withdraw = ExecuteMsg::Withdraw {
+            basket_name: "evm".to_string(),
+            percentage: 100,
+            remote_recipient: None,
+            destinations: vec![ChainDestination {
+                chain: "polygon".to_string(),
+                address: format!("0x{}", "ab".repeat(20)),
+            }],
+            zap_out: None,
+            owner: None,
+        };
+        let env = mock_env();
+        let res = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), withdraw).unwrap();
+
+        // Deposit and transfer travel in one self-call, so they share a reply.
+        assert_eq!(1, res.messages.len());
+        assert_eq!(ReplyOn::Always, res.messages[0].reply_on);
+        let CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, msg, .. }) = &res.messages[0].msg else {
+            panic!("expected a self-call");
+        };
+        assert_eq!(env.contract.address.as_str(), contract_addr);
+        let leg: ExecuteMsg = from_json(msg).unwrap();
+        let ExecuteMsg::ExecuteOutflowLeg { msgs } = leg.clone() else {
+            panic!("expected an outflow leg");
+        };
+        assert_eq!(2, msgs.len());
+        execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), leg).unwrap_err();
+
+        let failed = Reply {
+            id: OUTFLOW_REPLY_ID,
+            result: SubMsgResult::Err("initiate_transfer failed".to_string()),
+        };
+        reply(deps.as_mut(), env, failed).unwrap();
+        let portfolio = PORTFOLIOS.load(&deps.storage, "alice").unwrap();
+        assert_eq!(Uint128::new(1_000_000), portfolio.positions[0].shares);
+        assert!(PENDING_WITHDRAWALS.is_empty(&deps.storage));
+    }
+
+    #[test]
+    fn zap_out_swaps_each_leg_into_a_registered_token() {
+        let mut deps = setup();
+        let msg = create_basket_msg("core", vec![token("ARCH", 50), token("ATOM", 50)]);
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+        set_prices(deps.as_mut(), &[("ARCH", 2_000_000), ("ATOM", 10_000_000), ("USDC", 1_000_000)]);
+        let funds = [Coin::new(1_000_000, "uarch"), Coin::new(200_000, "uatom")];
+        invest_in_kind(deps.as_mut(), "alice", "core", &funds, false);
+        invest_in_kind(deps.as_mut(), "bob", "core", &funds, false);
+
+        let env = mock_env();
+        let zap_out = |min_output| ExecuteMsg::Withdraw {
+            basket_name: "core".to_string(),
+            percentage: 100,
+            remote_recipient: None,
+            destinations: vec![],
+            zap_out: Some(ZapOut { output_symbol: "USDC".to_string(), min_output: Uint128::new(min_output) }),
+            owner: None,
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), zap_out(0)).unwrap_err();
+        assert!(err.to_string().contains("USDC is not a registered token"));
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), ExecuteMsg::RegisterToken { token: token("USDC", 0).token })
+            .unwrap();
+
+        let swapped = |deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier<ArchwayQuery>, ArchwayQuery>, balance: u128| {
+            deps.querier.update_balance(env.contract.address.clone(), coins(balance, "uusdc"));
+            let ok = Reply { id: SWAP_REPLY_ID, result: SubMsgResult::Ok(SubMsgResponse { events: vec![], data: None }) };
+            reply(deps.as_mut(), env.clone(), ok)
+        };
+        let offered = |res: &Response<ArchwayMsg>| {
+            let CosmosMsg::Wasm(WasmMsg::Execute { funds, msg, .. }) = &res.messages[0].msg else {
+                panic!("expected a router swap");
+            };
+            let RouterExecuteMsg::Swap { minimum_receive, .. } = from_json(msg).unwrap();
+            (funds[0].clone(), minimum_receive)
+        };
+
+        // Each component is swapped in turn, each leg with its own oracle floor.
+        let res = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), zap_out(3_975_000)).unwrap();
+        assert_eq!((Coin::new(1_000_000, "uarch"), Uint128::new(1_980_000)), offered(&res));
+        let res = swapped(&mut deps, 1_990_000).unwrap();
+        assert_eq!((Coin::new(200_000, "uatom"), Uint128::new(1_980_000)), offered(&res));
+        let err = swapped(&mut deps, 1_990_000 + 1_900_000).unwrap_err();
+        assert!(err.to_string().contains("Swap into USDC returned 1900000, below minimum 1980000"));
+
+        // Legs that each clear their floor can still miss the withdrawal's.
+        let err = swapped(&mut deps, 1_990_000 + 1_980_000).unwrap_err();
+        assert!(err.to_string().contains("Zap-out returned 3970000 USDC, below minimum 3975000"));
+
+        let res = execute(deps.as_mut(), env.clone(), mock_info("bob", &[]), zap_out(3_900_000)).unwrap();
+        assert_eq!(SWAP_REPLY_ID, res.messages[0].id);
+        swapped(&mut deps, 3_970_000 + 1_990_000).unwrap();
+        let res = swapped(&mut deps, 3_970_000 + 1_990_000 + 1_980_000).unwrap();
+        assert_eq!(
+            CosmosMsg::Bank(BankMsg::Send { to_address: "bob".to_string(), amount: coins(3_970_000, "uusdc") }),
+            res.messages[0].msg
+        );
+        assert!(SWAP_JOB.may_load(&deps.storage).unwrap().is_none());
+    }
+
+    #[test]
+    fn withdraw_value_uses_market_value() {
+        let mut deps = setup();
+        let msg = create_basket_msg("core", vec![token("ARCH", 100)]);
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+        set_prices(deps.as_mut(), &[("ARCH", 1_000_000)]);
+        invest_in_kind(deps.as_mut(), "alice", "core", &coins(1_000_000, "uarch"), false);
+
+        // The position doubled in value since it was booked.
+        set_prices(deps.as_mut(), &[("ARCH", 2_000_000)]);
+        let withdraw = ExecuteMsg::WithdrawValue {
+            basket_name: "core".to_string(),
+            value: Uint128::new(1_000_000),
+            options: WithdrawOptions::default(),
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), withdraw).unwrap();
+        let data: WithdrawResponse = from_json(res.data.unwrap()).unwrap();
+        assert_eq!(Uint128::new(500_000), data.shares);
+        assert_eq!(Uint128::new(1_000_000), data.value);
+        assert_eq!(coins(500_000, "uarch"), data.payouts);
+
+        let withdraw = ExecuteMsg::WithdrawValue {
+            basket_name: "core".to_string(),
+            value: Uint128::new(1_000_001),
+            options: WithdrawOptions::default(),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), withdraw).unwrap_err();
+        assert!(err.to_string().contains("Position is only worth 1000000"));
+    }
+
+    #[test]
+    fn triggered_exit_is_recorded_once() {
+        let mut deps = setup();
+        let msg = create_basket_msg("core", vec![token("ARCH", 100)]);
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+        set_prices(deps.as_mut(), &[("ARCH", 1_000_000)]);
+        invest_in_kind(deps.as_mut(), "alice", "core", &coins(1_000_000, "uarch"), false);
+        let triggers = ExecuteMsg::SetExitTriggers {
+            basket_name: "core".to_string(),
+            stop_loss: Some(ExitThreshold::Percent { bps: 2_000 }),
+            take_profit: None,
+            destinations: vec![],
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), triggers).unwrap();
+
+        set_prices(deps.as_mut(), &[("ARCH", 500_000)]);
+        let exit = ExecuteMsg::TriggerExit { owner: "alice".to_string(), basket_name: "core".to_string() };
+        execute(deps.as_mut(), mock_env(), mock_info("keeper", &[]), exit).unwrap();
+
+        let history = PORTFOLIOS.load(&deps.storage, "alice").unwrap().investment_history;
+        let actions: Vec<_> = history.iter().map(|entry| entry.action.clone()).collect();
+        assert_eq!(vec![InvestmentAction::Deposit, InvestmentAction::StopLoss], actions);
+    }
+
+    #[test]
+    fn transfers_settle_harvest_and_drop_triggers_with_the_position() {
+        let mut deps = setup();
+        let msg = create_basket_msg("core", vec![token("ARCH", 100)]);
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+        set_prices(deps.as_mut(), &[("ARCH", 1_000_000)]);
+        invest_in_kind(deps.as_mut(), "alice", "core", &coins(1_000_000, "uarch"), false);
+        invest_in_kind(deps.as_mut(), "bob", "core", &coins(1_000_000, "uarch"), false);
+        let triggers = ExecuteMsg::SetExitTriggers {
+            basket_name: "core".to_string(),
+            stop_loss: Some(ExitThreshold::Percent { bps: 2_000 }),
+            take_profit: None,
+            destinations: vec![],
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), triggers).unwrap();
+        harvested(deps.as_mut(), coins(2_000_000, "aarch"));
+
+        let transfer = |recipient: &str, shares: Option<u128>| ExecuteMsg::TransferPosition {
+            basket_name: "core".to_string(),
+            recipient: recipient.to_string(),
+            shares: shares.map(Uint128::new),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), transfer("alice", None)).unwrap_err();
+        assert!(err.to_string().contains("Cannot transfer a position to yourself"));
+        let err = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), transfer("bob", Some(1_000_001)))
+            .unwrap_err();
+        assert!(err.to_string().contains("Insufficient shares"));
+
+        // A partial transfer keeps the sender's triggers and settles both sides
+        // at the old split before the shares move.
+        execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), transfer("bob", Some(250_000))).unwrap();
+        let alice = PORTFOLIOS.load(&deps.storage, "alice").unwrap();
+        let bob = PORTFOLIOS.load(&deps.storage, "bob").unwrap();
+        assert_eq!(Uint128::new(750_000), alice.positions[0].shares);
+        assert_eq!(Uint128::new(1_250_000), bob.positions[0].shares);
+        assert_eq!(Uint128::new(900_000), alice.harvest_pending["aarch"]);
+        assert_eq!(Uint128::new(900_000), bob.harvest_pending["aarch"]);
+        assert!(EXIT_TRIGGERS.has(&deps.storage, ("alice", "core")));
+
+        // Later harvests follow the new split.
+        harvested(deps.as_mut(), coins(2_000_000, "aarch"));
+
+        // A full transfer empties the position and drops its triggers.
+        execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), transfer("carol", None)).unwrap();
+        assert!(PORTFOLIOS.load(&deps.storage, "alice").unwrap().positions.is_empty());
+        assert!(!EXIT_TRIGGERS.has(&deps.storage, ("alice", "core")));
+        let carol = PORTFOLIOS.load(&deps.storage, "carol").unwrap();
+        assert_eq!(Uint128::new(750_000), carol.positions[0].shares);
+        assert_eq!(Uint128::new(750_000), carol.positions[0].current_value);
+
+        assert_eq!(coins(900_000 + 675_000, "aarch"), claim_harvest(deps.as_mut(), "alice"));
+        assert_eq!(coins(900_000 + 1_125_000, "aarch"), claim_harvest(deps.as_mut(), "bob"));
+        let err = execute(deps.as_mut(), mock_env(), mock_info("carol", &[]), ExecuteMsg::ClaimRewards { owner: None })
+            .unwrap_err();
+        assert!(err.to_string().contains("No rewards to claim"));
+    }
+
+    #[test]
+    fn only_admin_reactivates_a_basket() {
+        let mut deps = setup();
+        let msg = create_basket_msg("core", vec![token("ARCH", 100)]);
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+        let curation = Curation { curator: "carol".to_string(), bond: Coin::new(0, "uarch") };
+        BASKET_CURATORS.save(deps.as_mut().storage, "core", &curation).unwrap();
+
+        let deactivate = ExecuteMsg::UpdateBasket {
+            name: "core".to_string(),
+            tokens: vec![token("ARCH", 100)],
+            min_investment: None,
+            active: false,
+            drift_threshold_bps: None,
+            rebalance_interval: None,
+            fee_bps: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), deactivate).unwrap();
+
+        let reactivate = update_basket_msg("core", vec![token("ARCH", 100)]);
+        let err = execute(deps.as_mut(), mock_env(), mock_info("carol", &[]), reactivate.clone()).unwrap_err();
+        assert!(err.to_string().contains("Only the admin can reactivate a basket"));
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), reactivate).unwrap();
+        assert!(BASKETS.load(&deps.storage, "core").unwrap().active);
+    }
+
+    #[test]
+    fn basket_versions_resolve_by_number_or_time() {
+        let mut deps = setup();
+        let created = mock_env();
+        let t0 = created.block.time.seconds();
+        let msg = create_basket_msg("core", vec![token("ARCH", 100)]);
+        execute(deps.as_mut(), created, mock_info(ADMIN, &[]), msg).unwrap();
+
+        let mut later = mock_env();
+        later.block.time = later.block.time.plus_seconds(100);
+        let msg = update_basket_msg("core", vec![token("ARCH", 50), token("ATOM", 50)]);
+        execute(deps.as_mut(), later.clone(), mock_info(ADMIN, &[]), msg.clone()).unwrap();
+        // An update that keeps the composition records no new version.
+        later.block.time = later.block.time.plus_seconds(100);
+        execute(deps.as_mut(), later, mock_info(ADMIN, &[]), msg).unwrap();
+
+        let lookup = |deps: &OwnedDeps<MockStorage, MockApi, MockQuerier<ArchwayQuery>, ArchwayQuery>, version, at_time| {
+            let msg = QueryMsg::GetBasketVersion { basket_name: "core".to_string(), version, at_time };
+            query(deps.as_ref(), mock_env(), msg).and_then(from_json::<BasketVersion>)
+        };
+        let v1 = lookup(&deps, Some(1), None).unwrap();
+        assert_eq!((vec![token("ARCH", 100)], t0, ADMIN.to_string()), (v1.tokens.clone(), v1.created_at, v1.author.clone()));
+        assert_eq!(2, lookup(&deps, Some(2), None).unwrap().version);
+        let err = lookup(&deps, Some(3), None).unwrap_err();
+        assert!(err.to_string().contains("No such version of basket core"));
+
+        assert_eq!(v1, lookup(&deps, None, Some(t0)).unwrap());
+        assert_eq!(v1, lookup(&deps, None, Some(t0 + 99)).unwrap());
+        assert_eq!(2, lookup(&deps, None, Some(t0 + 100)).unwrap().version);
+        assert_eq!(2, lookup(&deps, None, Some(t0 + 1_000)).unwrap().version);
+        let err = lookup(&deps, None, Some(t0 - 1)).unwrap_err();
+        assert!(err.to_string().contains("No such version of basket core"));
+        let err = lookup(&deps, Some(1), Some(t0)).unwrap_err();
+        assert!(err.to_string().contains("Specify exactly one of version or at_time"));
+
+        let all: Vec<BasketVersion> = from_json(
+            query(deps.as_ref(), mock_env(), QueryMsg::ListBasketVersions { basket_name: "core".to_string() }).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(vec![1, 2], all.iter().map(|v| v.version).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn deprecated_basket_cannot_be_reactivated() {
+        let mut deps = setup();
+        for name in ["old", "new"] {
+            execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), create_basket_msg(name, vec![token("ARCH", 100)]))
+                .unwrap();
+        }
+        let deprecate = ExecuteMsg::DeprecateBasket { basket_name: "old".to_string(), successor: "new".to_string() };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), deprecate).unwrap();
+
+        // Rejected before the new composition is recorded.
+        let msg = update_basket_msg("old", vec![token("ARCH", 50), token("ATOM", 50)]);
+        let err = execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap_err();
+        assert!(err.to_string().contains("Deprecated baskets cannot be reactivated"));
+        let basket = BASKETS.load(&deps.storage, "old").unwrap();
+        assert_eq!((1, false), (basket.version, basket.active));
+        assert!(!BASKET_VERSIONS.has(&deps.storage, ("old", 2)));
+    }
+
+    #[test]
+    fn successor_keeps_components_and_must_stay_active() {
+        let mut deps = setup();
+        for (name, tokens) in [("old", vec![token("ARCH", 100)]), ("new", vec![token("ARCH", 50), token("ATOM", 50)])] {
+            execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), create_basket_msg(name, tokens)).unwrap();
+        }
+        set_prices(deps.as_mut(), &[("ARCH", 1_000_000), ("ATOM", 1_000_000)]);
+        invest_in_kind(deps.as_mut(), "alice", "old", &coins(1_000_000, "uarch"), false);
+        let deprecate = ExecuteMsg::DeprecateBasket { basket_name: "old".to_string(), successor: "new".to_string() };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), deprecate).unwrap();
+
+        let msg = update_basket_msg("new", vec![token("ATOM", 100)]);
+        let err = execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap_err();
+        assert!(err.to_string().contains("Deprecated basket old migrates into this one and needs its ARCH token"));
+
+        let pause = ExecuteMsg::UpdateBasket {
+            name: "new".to_string(),
+            tokens: vec![token("ARCH", 50), token("ATOM", 50)],
+            min_investment: None,
+            active: false,
+            drift_threshold_bps: None,
+            rebalance_interval: None,
+            fee_bps: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), pause).unwrap();
+        let migrate = ExecuteMsg::MigratePosition { basket_name: "old".to_string() };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), migrate).unwrap_err();
+        assert!(err.to_string().contains("Successor basket is not active"));
+        let portfolio = PORTFOLIOS.load(&deps.storage, "alice").unwrap();
+        assert_eq!("old", portfolio.positions[0].basket_name);
+    }
+
+    #[test]
+    fn migrate_holders_resumes_from_its_cursor_at_fresh_prices() {
+        let mut deps = setup();
+        for name in ["old", "new"] {
+            execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), create_basket_msg(name, vec![token("ARCH", 100)]))
+                .unwrap();
+        }
+        set_prices(deps.as_mut(), &[("ARCH", 1_000_000)]);
+        for user in ["alice", "bob", "carol"] {
+            invest_in_kind(deps.as_mut(), user, "old", &coins(1_000_000, "uarch"), false);
+        }
+        invest_in_kind(deps.as_mut(), "dave", "new", &coins(1_000_000, "uarch"), false);
+        let deprecate = ExecuteMsg::DeprecateBasket { basket_name: "old".to_string(), successor: "new".to_string() };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), deprecate).unwrap();
+
+        let migrate = ExecuteMsg::MigrateHolders { basket_name: "old".to_string(), limit: Some(2) };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("keeper", &[]), migrate.clone()).unwrap();
+        assert!(res.attributes.contains(&Attribute::new("migrated", "2")));
+        assert!(res.attributes.contains(&Attribute::new("done", "false")));
+        assert_eq!("bob", MIGRATION_CURSORS.load(&deps.storage, "old").unwrap());
+
+        // Stale prices hold the migration back without moving the cursor.
+        let mut later = mock_env();
+        later.block.time = later.block.time.plus_seconds(10);
+        let err = execute(deps.as_mut(), later.clone(), mock_info("keeper", &[]), migrate.clone()).unwrap_err();
+        assert!(err.to_string().contains("Price for ARCH is stale"));
+        assert_eq!("bob", MIGRATION_CURSORS.load(&deps.storage, "old").unwrap());
+
+        let prices = vec![PriceUpdate { symbol: "ARCH".to_string(), price: Uint128::new(1_000_000) }];
+        execute(deps.as_mut(), later.clone(), mock_info(ADMIN, &[]), ExecuteMsg::UpdatePrices { prices }).unwrap();
+        let res = execute(deps.as_mut(), later.clone(), mock_info("keeper", &[]), migrate.clone()).unwrap();
+        assert!(res.attributes.contains(&Attribute::new("scanned", "2")));
+        assert!(res.attributes.contains(&Attribute::new("migrated", "1")));
+        assert_eq!("dave", MIGRATION_CURSORS.load(&deps.storage, "old").unwrap());
+        let res = execute(deps.as_mut(), later, mock_info("keeper", &[]), migrate).unwrap();
+        assert!(res.attributes.contains(&Attribute::new("done", "true")));
+        assert!(!MIGRATION_CURSORS.has(&deps.storage, "old"));
+
+        for user in ["alice", "bob", "carol", "dave"] {
+            let portfolio = PORTFOLIOS.load(&deps.storage, user).unwrap();
+            assert_eq!(vec!["new"], portfolio.positions.iter().map(|p| p.basket_name.as_str()).collect::<Vec<_>>());
+        }
+        assert!(BASKETS.load(&deps.storage, "old").unwrap().total_shares.is_zero());
+    }
+
+    #[test]
+    fn set_flat_fee_targets_this_contract() {
+        let mut deps = setup();
+        let fee = Coin::new(100, "uarch");
+        let env = mock_env();
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(ADMIN, &[]),
+            ExecuteMsg::SetArchwayFlatFee { amount: fee.clone() },
+        )
+        .unwrap();
+        assert_eq!(
+            CosmosMsg::Custom(ArchwayMsg::set_flat_fee(env.contract.address, fee)),
+            res.messages[0].msg
+        );
+    }
 }
@@ -0,0 +1,378 @@
+#[cfg(not(feature = "library"))]
+use cosmwasm_std::entry_point;
+use archway_bindings::{ArchwayMsg, ArchwayQuery};
+use cosmwasm_std::{
+    from_json, to_json_binary, Binary, ChannelResponse, Deps, DepsMut, Env, Ibc3ChannelOpenResponse, IbcBasicResponse,
+    IbcChannel, IbcChannelCloseMsg, IbcChannelConnectMsg, IbcChannelOpenMsg,
+    IbcChannelOpenResponse, IbcOrder, IbcPacketAckMsg, IbcPacketReceiveMsg, IbcPacketTimeoutMsg,
+    IbcQuery, IbcReceiveResponse, MessageInfo, Order, QueryRequest, Reply, Response, StdError, StdResult, Storage, SubMsg,
+    SubMsgResult, Uint128, WasmMsg,
+};
+use cw_storage_plus::{Item, Map};
+use std::collections::HashMap;
+
+use crate::address::{bech32_prefix, ibc_hooks_sender};
+use crate::contract::{
+    check_payout_routes, ensure_ibc_routable, generate_withdraw_messages, ics20_channel, invest_in_kind_for, is_component,
+    position_shares, preview_payouts, redeem_position, track_outflows, zap_into_basket, BASKETS, CONFIG,
+    InvestmentAction, IBC_PACKET_REPLY_ID,
+};
+use crate::msg::{
+    BasketPacket, BasketPacketAck, BasketPacketMsg, BasketPacketResult, ExecuteMsg,
+    IbcCounterparty,
+};
+
+pub const IBC_APP_VERSION: &str = "euclidfi-baskets-1";
+/// Port the chain's ICS-20 transfer module is bound to.
+const TRANSFER_PORT: &str = "transfer";
+pub const PACKET_VERSION: u32 = 1;
+
+/// Open basket channels, keyed by local channel id.
+pub const IBC_CHANNELS: Map<&str, IbcChannel> = Map::new("ibc_channels");
+/// Counterparty ports and connections basket channels may be opened with.
+pub const IBC_COUNTERPARTIES: Map<(&str, &str), ()> = Map::new("ibc_counterparties");
+/// Ack produced by the packet currently being processed, read back in the reply.
+const IBC_PACKET_ACK: Item<Binary> = Item::new("ibc_packet_ack");
+
+/// Portfolio key for a user on the other end of `channel_id`.
+pub fn remote_owner(channel_id: &str, sender: &str) -> String {
+    format!("{}/{}", channel_id, sender)
+}
+
+fn ack_result(result: &BasketPacketResult) -> StdResult<Binary> {
+    to_json_binary(&BasketPacketAck::Result(to_json_binary(result)?))
+}
+
+fn ack_error(err: impl Into<String>) -> StdResult<Binary> {
+    to_json_binary(&BasketPacketAck::Error(err.into()))
+}
+
+fn ensure_counterparty_allowed(storage: &dyn Storage, channel: &IbcChannel) -> StdResult<()> {
+    let port_id = &channel.counterparty_endpoint.port_id;
+    if !IBC_COUNTERPARTIES.has(storage, (port_id, &channel.connection_id)) {
+        return Err(StdError::generic_err(format!(
+            "Counterparty {} on {} is not allowed",
+            port_id, channel.connection_id
+        )));
+    }
+    Ok(())
+}
+
+fn validate_channel(
+    storage: &dyn Storage,
+    channel: &IbcChannel,
+    counterparty_version: Option<&str>,
+) -> StdResult<()> {
+    ensure_counterparty_allowed(storage, channel)?;
+    if channel.order != IbcOrder::Unordered {
+        return Err(StdError::generic_err("Basket channels must be unordered"));
+    }
+    if channel.version != IBC_APP_VERSION {
+        return Err(StdError::generic_err(format!(
+            "Channel version must be {}",
+            IBC_APP_VERSION
+        )));
+    }
+    if let Some(version) = counterparty_version {
+        if version != IBC_APP_VERSION {
+            return Err(StdError::generic_err(format!(
+                "Counterparty version must be {}",
+                IBC_APP_VERSION
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_channel_open(
+    deps: DepsMut<ArchwayQuery>,
+    _env: Env,
+    msg: IbcChannelOpenMsg,
+) -> StdResult<IbcChannelOpenResponse> {
+    validate_channel(deps.storage, msg.channel(), msg.counterparty_version())?;
+
+    Ok(Some(Ibc3ChannelOpenResponse {
+        version: IBC_APP_VERSION.to_string(),
+    }))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_channel_connect(
+    deps: DepsMut<ArchwayQuery>,
+    _env: Env,
+    msg: IbcChannelConnectMsg,
+) -> StdResult<IbcBasicResponse<ArchwayMsg>> {
+    let channel = msg.channel();
+    validate_channel(deps.storage, channel, msg.counterparty_version())?;
+
+    IBC_CHANNELS.save(deps.storage, &channel.endpoint.channel_id, channel)?;
+
+    Ok(IbcBasicResponse::new()
+        .add_attribute("action", "ibc_channel_connect")
+        .add_attribute("channel", &channel.endpoint.channel_id))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_channel_close(
+    deps: DepsMut<ArchwayQuery>,
+    _env: Env,
+    msg: IbcChannelCloseMsg,
+) -> StdResult<IbcBasicResponse<ArchwayMsg>> {
+    let channel = msg.channel();
+    IBC_CHANNELS.remove(deps.storage, &channel.endpoint.channel_id);
+
+    Ok(IbcBasicResponse::new()
+        .add_attribute("action", "ibc_channel_close")
+        .add_attribute("channel", &channel.endpoint.channel_id))
+}
+
+/// Packets are applied through a self-call so that a failure reverts every
+/// state change it made; the reply then turns the outcome into the ack.
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_packet_receive(
+    deps: DepsMut<ArchwayQuery>,
+    env: Env,
+    msg: IbcPacketReceiveMsg,
+) -> StdResult<IbcReceiveResponse<ArchwayMsg>> {
+    let channel_id = msg.packet.dest.channel_id;
+    let response = IbcReceiveResponse::new().add_attribute("action", "ibc_packet_receive");
+
+    let channel = match IBC_CHANNELS.may_load(deps.storage, &channel_id)? {
+        Some(channel) => channel,
+        None => return Ok(response.set_ack(ack_error(format!("Unknown channel {}", channel_id))?)),
+    };
+    // Disallowing a counterparty also stops its channels that are already open.
+    if let Err(err) = ensure_counterparty_allowed(deps.storage, &channel) {
+        return Ok(response.set_ack(ack_error(err.to_string())?));
+    }
+    let packet: BasketPacket = match from_json(&msg.packet.data) {
+        Ok(packet) => packet,
+        Err(err) => return Ok(response.set_ack(ack_error(err.to_string())?)),
+    };
+    if packet.version != PACKET_VERSION {
+        return Ok(response.set_ack(ack_error(format!(
+            "Unsupported packet version {}",
+            packet.version
+        ))?));
+    }
+
+    let process = WasmMsg::Execute {
+        contract_addr: env.contract.address.to_string(),
+        msg: to_json_binary(&ExecuteMsg::ProcessIbcPacket { channel_id, packet })?,
+        funds: vec![],
+    };
+
+    Ok(response
+        .set_ack(ack_error("Packet was not processed")?)
+        .add_submessage(SubMsg::reply_always(process, IBC_PACKET_REPLY_ID)))
+}
+
+/// We never send packets on basket channels, so there is nothing to settle.
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_packet_ack(
+    _deps: DepsMut<ArchwayQuery>,
+    _env: Env,
+    _msg: IbcPacketAckMsg,
+) -> StdResult<IbcBasicResponse<ArchwayMsg>> {
+    Ok(IbcBasicResponse::new().add_attribute("action", "ibc_packet_ack"))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_packet_timeout(
+    _deps: DepsMut<ArchwayQuery>,
+    _env: Env,
+    _msg: IbcPacketTimeoutMsg,
+) -> StdResult<IbcBasicResponse<ArchwayMsg>> {
+    Ok(IbcBasicResponse::new().add_attribute("action", "ibc_packet_timeout"))
+}
+
+pub fn execute_process_ibc_packet(
+    mut deps: DepsMut<ArchwayQuery>,
+    env: Env,
+    info: MessageInfo,
+    channel_id: String,
+    packet: BasketPacket,
+) -> StdResult<Response<ArchwayMsg>> {
+    if info.sender != env.contract.address {
+        return Err(StdError::generic_err("Unauthorized"));
+    }
+
+    let mut response = Response::new()
+        .add_attribute("action", "process_ibc_packet")
+        .add_attribute("channel", &channel_id);
+
+    let result = match packet.msg {
+        BasketPacketMsg::Withdraw {
+            sender,
+            basket_name,
+            shares,
+        } => {
+            let owner = remote_owner(&channel_id, &sender);
+
+            // Remote users can only be paid over ICS-20, to an address valid on
+            // each token's chain.
+            let basket = BASKETS.load(deps.storage, &basket_name)?;
+            let payouts = preview_payouts(deps.storage, &basket, shares)?;
+            ensure_ibc_routable(deps.storage, &basket, &payouts)?;
+            check_payout_routes(deps.storage, &basket, &payouts, &HashMap::new(), Some(&sender))?;
+
+            let redemption = redeem_position(
+                deps.branch(),
+                &env,
+                &owner,
+                &basket_name,
+                shares,
+                InvestmentAction::Withdraw,
+            )?;
+
+            let outflows = generate_withdraw_messages(
+                deps.storage,
+                &env,
+                &redemption.basket,
+                &owner,
+                Some(&sender),
+                &HashMap::new(),
+                &redemption.payouts,
+            )?;
+            track_outflows(deps.storage, &outflows)?;
+            response = response.add_submessages(outflows.messages);
+
+            BasketPacketResult {
+                shares,
+                position_shares: position_shares(deps.as_ref(), &owner, &basket_name)
+                    .unwrap_or_default(),
+                value: redemption.value,
+            }
+        }
+    };
+
+    IBC_PACKET_ACK.save(deps.storage, &ack_result(&result)?)?;
+
+    Ok(response
+        .add_attribute("shares", result.shares.to_string())
+        .add_attribute("value", result.value.to_string()))
+}
+
+pub fn execute_set_ibc_counterparty(
+    deps: DepsMut<ArchwayQuery>,
+    info: MessageInfo,
+    counterparty: IbcCounterparty,
+    allowed: bool,
+) -> StdResult<Response<ArchwayMsg>> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(StdError::generic_err("Unauthorized"));
+    }
+    if counterparty.port_id.is_empty() || counterparty.connection_id.is_empty() {
+        return Err(StdError::generic_err("Counterparty port and connection cannot be empty"));
+    }
+
+    let key = (counterparty.port_id.as_str(), counterparty.connection_id.as_str());
+    if allowed {
+        IBC_COUNTERPARTIES.save(deps.storage, key, &())?;
+    } else {
+        IBC_COUNTERPARTIES.remove(deps.storage, key);
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "set_ibc_counterparty")
+        .add_attribute("port_id", counterparty.port_id)
+        .add_attribute("connection_id", counterparty.connection_id)
+        .add_attribute("allowed", allowed.to_string()))
+}
+
+pub fn query_ibc_counterparties(deps: Deps<ArchwayQuery>) -> StdResult<Vec<IbcCounterparty>> {
+    IBC_COUNTERPARTIES
+        .keys(deps.storage, None, None, Order::Ascending)
+        .map(|key| key.map(|(port_id, connection_id)| IbcCounterparty { port_id, connection_id }))
+        .collect()
+}
+
+/// IBC-hooks executes a transfer's memo from an address derived from the
+/// local transfer channel and the original sender, so matching it proves the
+/// attached coin was sent by `sender` over that channel.
+#[allow(clippy::too_many_arguments)]
+pub fn execute_ibc_invest(
+    deps: DepsMut<ArchwayQuery>,
+    env: Env,
+    info: MessageInfo,
+    channel_id: String,
+    sender: String,
+    basket_name: String,
+    min_value: Uint128,
+    auto_compound: bool,
+) -> StdResult<Response<ArchwayMsg>> {
+    let channel = IBC_CHANNELS
+        .may_load(deps.storage, &channel_id)?
+        .ok_or_else(|| StdError::generic_err(format!("Unknown channel {}", channel_id)))?;
+    ensure_counterparty_allowed(deps.storage, &channel)?;
+    let input = match info.funds.as_slice() {
+        [coin] if !coin.amount.is_zero() => coin.clone(),
+        _ => return Err(StdError::generic_err("IBC invest takes exactly one transferred coin")),
+    };
+    let basket = BASKETS.load(deps.storage, &basket_name)?;
+    let transfer_channel = ics20_channel(deps.storage, &basket, &input.denom)?;
+
+    let prefix = bech32_prefix(env.contract.address.as_str())?;
+    if info.sender != ibc_hooks_sender(&transfer_channel, &sender, &prefix)? {
+        return Err(StdError::generic_err("Unauthorized"));
+    }
+
+    // The position is keyed to the basket channel, so the funds must come from
+    // the same chain: their transfer channel has to share its connection.
+    let transfer: ChannelResponse = deps.querier.query(&QueryRequest::Ibc(IbcQuery::Channel {
+        channel_id: transfer_channel.clone(),
+        port_id: Some(TRANSFER_PORT.to_string()),
+    }))?;
+    let transfer_connection = transfer
+        .channel
+        .map(|channel| channel.connection_id)
+        .ok_or_else(|| StdError::generic_err(format!("Unknown transfer channel {}", transfer_channel)))?;
+    if transfer_connection != channel.connection_id {
+        return Err(StdError::generic_err(format!(
+            "Transfer channel {} is not on {}'s connection {}",
+            transfer_channel, channel_id, channel.connection_id
+        )));
+    }
+
+    let owner = remote_owner(&channel_id, &sender);
+    if !is_component(&basket, &input.denom) {
+        return zap_into_basket(deps, env, owner, basket_name, input, min_value, auto_compound);
+    }
+    let (shares, value) = invest_in_kind_for(
+        deps,
+        &env,
+        &owner,
+        &basket_name,
+        &[input],
+        min_value,
+        None,
+        auto_compound,
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "ibc_invest")
+        .add_attribute("channel", channel_id)
+        .add_attribute("basket", basket_name)
+        .add_attribute("amount", value.to_string())
+        .add_attribute("shares", shares.to_string()))
+}
+
+/// Data set here replaces the ack of the packet being received.
+pub fn reply_ibc_packet(
+    deps: DepsMut<ArchwayQuery>,
+    _env: Env,
+    msg: Reply,
+) -> StdResult<Response<ArchwayMsg>> {
+    let ack = match msg.result {
+        SubMsgResult::Ok(_) => {
+            let ack = IBC_PACKET_ACK.load(deps.storage)?;
+            IBC_PACKET_ACK.remove(deps.storage);
+            ack
+        }
+        SubMsgResult::Err(err) => ack_error(err)?,
+    };
+
+    Ok(Response::new().set_data(ack))
+}
@@ -1,71 +1,102 @@
 #[cfg(test)]
 mod tests {
-    use crate::helpers::CwTemplateContract;
-    use crate::msg::InstantiateMsg;
-    use cosmwasm_std::{Addr, Coin, Empty, Uint128};
-    use cw_multi_test::{App, AppBuilder, Contract, ContractWrapper, Executor};
+    use crate::msg::{ExecuteMsg, InstantiateMsg, QueryMsg};
+    use archway_bindings::{ArchwayMsg, ArchwayQuery};
+    use cosmwasm_std::{from_json, Addr, Uint128};
+    use cw_multi_test::{BasicApp, BasicAppBuilder, Contract, ContractWrapper, Executor};
 
-    pub fn contract_template() -> Box<dyn Contract<Empty>> {
+    pub fn contract_euclidfi() -> Box<dyn Contract<ArchwayMsg, ArchwayQuery>> {
         let contract = ContractWrapper::new(
             crate::contract::execute,
             crate::contract::instantiate,
             crate::contract::query,
-        );
+        )
+        .with_reply(crate::contract::reply);
         Box::new(contract)
     }
 
     const USER: &str = "USER";
     const ADMIN: &str = "ADMIN";
-    const NATIVE_DENOM: &str = "denom";
 
-    fn mock_app() -> App {
-        AppBuilder::new().build(|router, _, storage| {
-            router
-                .bank
-                .init_balance(
-                    storage,
-                    &Addr::unchecked(USER),
-                    vec![Coin {
-                        denom: NATIVE_DENOM.to_string(),
-                        amount: Uint128::new(1),
-                    }],
-                )
-                .unwrap();
-        })
+    fn mock_app() -> BasicApp<ArchwayMsg, ArchwayQuery> {
+        BasicAppBuilder::<ArchwayMsg, ArchwayQuery>::new_custom().build(|_, _, _| {})
     }
 
-    fn proper_instantiate() -> (App, CwTemplateContract) {
+    fn proper_instantiate() -> (BasicApp<ArchwayMsg, ArchwayQuery>, Addr) {
         let mut app = mock_app();
-        let cw_template_id = app.store_code(contract_template());
+        let code_id = app.store_code(contract_euclidfi());
 
-        let msg = InstantiateMsg { count: 1i32 };
-        let cw_template_contract_addr = app
-            .instantiate_contract(
-                cw_template_id,
-                Addr::unchecked(ADMIN),
-                &msg,
-                &[],
-                "test",
-                None,
-            )
+        let msg = InstantiateMsg {
+            admin: ADMIN.to_string(),
+            reward_token: "ureward".to_string(),
+            reward_rate: Uint128::new(500),
+            min_lock_period: 0,
+            compound_frequency: 86_400,
+            keeper_tip_bps: 50,
+            treasury: "treasury".to_string(),
+            harvest_treasury_bps: 1_000,
+            dex_router: None,
+            max_slippage_bps: 100,
+            ibc_timeout_seconds: 600,
+            oracle: None,
+            price_max_age: 0,
+        };
+        let addr = app
+            .instantiate_contract(code_id, Addr::unchecked(ADMIN), &msg, &[], "euclidfi", None)
             .unwrap();
 
-        let cw_template_contract = CwTemplateContract(cw_template_contract_addr);
-
-        (app, cw_template_contract)
+        (app, addr)
     }
 
-    mod count {
+    mod baskets {
         use super::*;
-        use crate::msg::ExecuteMsg;
+        use serde::Deserialize;
+
+        #[derive(Deserialize)]
+        struct BasketStatus {
+            active: bool,
+        }
+
+        fn set_chain() -> ExecuteMsg {
+            from_json(
+                br#"{"set_chain":{"chain":{"id":"archway","display_name":"Archway",
+                "address_format":{"Bech32":{"prefix":"archway"}},"bridge":null,"enabled":true}}}"#,
+            )
+            .unwrap()
+        }
+
+        fn create_basket(name: &str) -> ExecuteMsg {
+            from_json(format!(
+                r#"{{"create_basket":{{"name":"{}","min_investment":"1","drift_threshold_bps":500,
+                "rebalance_interval":0,"tokens":[{{"weight":100,"token":{{"address":"uarch",
+                "chain":"archway","symbol":"ARCH","decimals":6,"ibc":null}}}}]}}}}"#,
+                name
+            ))
+            .unwrap()
+        }
 
         #[test]
-        fn count() {
-            let (mut app, cw_template_contract) = proper_instantiate();
+        fn admin_creates_basket() {
+            let (mut app, addr) = proper_instantiate();
+            app.execute_contract(Addr::unchecked(ADMIN), addr.clone(), &set_chain(), &[])
+                .unwrap();
+            app.execute_contract(Addr::unchecked(ADMIN), addr.clone(), &create_basket("core"), &[])
+                .unwrap();
+
+            let basket: BasketStatus = app
+                .wrap()
+                .query_wasm_smart(addr, &QueryMsg::GetBasket { name: "core".to_string() })
+                .unwrap();
+            assert!(basket.active);
+        }
 
-            let msg = ExecuteMsg::Increment {};
-            let cosmos_msg = cw_template_contract.call(msg).unwrap();
-            app.execute(Addr::unchecked(USER), cosmos_msg).unwrap();
+        #[test]
+        fn user_cannot_create_basket_without_bond_policy() {
+            let (mut app, addr) = proper_instantiate();
+            app.execute_contract(Addr::unchecked(ADMIN), addr.clone(), &set_chain(), &[])
+                .unwrap();
+            app.execute_contract(Addr::unchecked(USER), addr, &create_basket("mine"), &[])
+                .unwrap_err();
         }
     }
 }
@@ -1,6 +1,8 @@
+pub mod address;
 pub mod contract;
 mod error;
 pub mod helpers;
+pub mod ibc;
 pub mod integration_tests;
 pub mod msg;
 pub mod state;
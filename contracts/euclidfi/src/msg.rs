@@ -1,14 +1,16 @@
 use cosmwasm_schema::{cw_serde, QueryResponses};
+use archway_bindings::ArchwayMsg;
 use cosmwasm_std::{
-    entry_point, to_binary, Binary, Deps, DepsMut, Env, MessageInfo,
-    Response, StdResult, Uint128, CosmosMsg, WasmMsg, QueryRequest, StdError, Order,
+    Binary, Coin, CosmosMsg, HexBinary, Uint128,
 };
-use cw_storage_plus::{Item, Map};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
 
-use crate::contract::TokenWeight;
+use crate::contract::{
+    BasketConfig, BasketVersion, ChainInfo, Config, Curation, CuratorPolicy, DcaEnd, DcaSchedule,
+    ExitThreshold, ExitTriggers, InvestmentHistory, OperatorApproval, OperatorPermission,
+    PendingWithdrawal, Token, TokenWeight, UserPortfolio,
+};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct InstantiateMsg {
@@ -17,73 +19,571 @@ pub struct InstantiateMsg {
     pub reward_rate: Uint128,
     pub min_lock_period: u64,
     pub compound_frequency: u64,
+    pub keeper_tip_bps: u16,
+    pub treasury: String,
+    pub harvest_treasury_bps: u16,
+    pub dex_router: Option<String>,
+    pub max_slippage_bps: u16,
+    pub ibc_timeout_seconds: u64,
+    pub oracle: Option<String>,
+    pub price_max_age: u64,
 }
 
 #[cw_serde]
 pub enum ExecuteMsg {
+    /// Admin, or anyone posting the curator bond for a basket built from
+    /// registered tokens within the curator policy.
     CreateBasket {
         name: String,
         tokens: Vec<TokenWeight>,
         min_investment: Uint128,
+        drift_threshold_bps: u16,
+        rebalance_interval: u64,
+        #[serde(default)]
+        fee_bps: u16,
     },
+    /// Admin, or the basket's curator within the curator policy. Only the
+    /// admin can reactivate an inactive basket.
     UpdateBasket {
         name: String,
         tokens: Vec<TokenWeight>,
         min_investment: Option<Uint128>,
         active: bool,
+        drift_threshold_bps: Option<u16>,
+        rebalance_interval: Option<u64>,
+        fee_bps: Option<u16>,
+    },
+    /// Closes a basket to deposits and names the basket its holders migrate
+    /// to. Admin only.
+    DeprecateBasket {
+        basket_name: String,
+        successor: String,
+    },
+    /// Moves the sender's position in a deprecated basket into its successor.
+    MigratePosition {
+        basket_name: String,
+    },
+    /// Migrates the next `limit` holders of a deprecated basket. Anyone may call it.
+    MigrateHolders {
+        basket_name: String,
+        limit: Option<u32>,
+    },
+    /// Refunds a curator's bond once their basket is inactive and empty.
+    ReclaimBond {
+        basket_name: String,
     },
+    /// Approves a token for curated baskets. Admin only.
+    RegisterToken {
+        token: Token,
+    },
+    DeregisterToken {
+        symbol: String,
+    },
+    SetCuratorPolicy {
+        policy: CuratorPolicy,
+    },
+    /// Deposits the attached tokens in kind, or swaps a single attached coin
+    /// into the components with `zap_in`.
     Invest {
         basket_name: String,
-        amount: Uint128,
+        /// Reject the deposit unless it is worth at least this much at oracle prices.
+        #[serde(default)]
+        min_value: Uint128,
         auto_compound: bool,
+        /// Only for in-kind deposits: reject them unless every component is
+        /// within this many basis points of its basket weight.
+        #[serde(default)]
+        in_kind_tolerance_bps: Option<u16>,
+        /// Swap the single attached coin into the basket's components through
+        /// the DEX router instead of depositing it in kind.
+        #[serde(default)]
+        zap_in: bool,
     },
     Withdraw {
         basket_name: String,
         percentage: u8,
+        /// Receiver on the tokens' origin chain. Tokens with an IBC route are sent
+        /// there over ICS-20; any other unbridged token rejects the withdrawal.
+        remote_recipient: Option<String>,
+        /// Receiver for each bridged chain the withdrawal pays out on.
+        #[serde(default)]
+        destinations: Vec<ChainDestination>,
+        /// Swap every component into one token and pay that out instead.
+        #[serde(default)]
+        zap_out: Option<ZapOut>,
+        /// Withdraw from this owner's position as their approved operator.
+        #[serde(default)]
+        owner: Option<String>,
+    },
+    /// Redeems an exact number of shares.
+    WithdrawShares {
+        basket_name: String,
+        shares: Uint128,
+        #[serde(default)]
+        options: WithdrawOptions,
+    },
+    /// Redeems the fewest shares whose market value is at least `value`.
+    WithdrawValue {
+        basket_name: String,
+        value: Uint128,
+        #[serde(default)]
+        options: WithdrawOptions,
+    },
+    /// Redeems the whole position.
+    WithdrawMax {
+        basket_name: String,
+        #[serde(default)]
+        options: WithdrawOptions,
+    },
+    /// Moves `shares` of a position, or all of it, to another address.
+    TransferPosition {
+        basket_name: String,
+        recipient: String,
+        shares: Option<Uint128>,
+    },
+    ClaimRewards {
+        /// Claim for this owner as their approved operator; rewards go to the owner.
+        #[serde(default)]
+        owner: Option<String>,
     },
-    ClaimRewards {},
     SetAutoCompound {
         basket_name: String,
         enabled: bool,
+        #[serde(default)]
+        owner: Option<String>,
     },
+    /// Lets `operator` act on the sender's portfolio within `permissions`
+    /// until `expires` (block time in seconds). Replaces any earlier approval.
+    ApproveOperator {
+        operator: String,
+        permissions: Vec<OperatorPermission>,
+        expires: Option<u64>,
+    },
+    RevokeOperator {
+        operator: String,
+    },
+    /// Rebalances a basket's pooled holdings. Permissionless, but only allowed
+    /// once drift exceeds the basket's band or its rebalance interval elapsed.
     Rebalance {
         basket_name: String,
     },
-    UpdateConfig {
-        reward_rate: Option<Uint128>,
-        min_lock_period: Option<u64>,
-        compound_frequency: Option<u64>,
+    /// Swaps accrued rewards of the basket's auto-compounding positions into
+    /// its components out of the reward pool, paying the caller a tip.
+    Compound {
+        basket_name: String,
+        limit: Option<u32>,
+    },
+    /// Adds the attached reward tokens to the pool that pays claims and
+    /// compounding.
+    FundRewards {},
+    /// Publishes oracle prices, quoted per whole token. Admin or oracle only.
+    UpdatePrices {
+        prices: Vec<PriceUpdate>,
+    },
+    /// Sets or, with neither threshold, clears automatic exits on a position.
+    SetExitTriggers {
+        basket_name: String,
+        stop_loss: Option<ExitThreshold>,
+        take_profit: Option<ExitThreshold>,
+        #[serde(default)]
+        destinations: Vec<ChainDestination>,
+    },
+    /// Withdraws `owner`'s position if one of its exit thresholds is crossed.
+    TriggerExit {
+        owner: String,
+        basket_name: String,
+    },
+    /// Escrows the attached coin and invests `amount_per_run` of it into the
+    /// basket every `interval` seconds until `end`.
+    CreateDca {
+        basket_name: String,
+        amount_per_run: Uint128,
+        interval: u64,
+        end: DcaEnd,
+        auto_compound: bool,
+    },
+    /// Stops a schedule and refunds its unspent escrow. Owner only.
+    CancelDca {
+        id: u64,
+    },
+    /// Executes due DCA schedules, paying the caller a tip.
+    RunDca {
+        limit: Option<u32>,
+    },
+    /// Invests one run of a DCA schedule and tips `keeper`. Only callable by
+    /// the contract itself, so a failing run reverts as a unit.
+    ExecuteDcaRun {
+        id: u64,
+        keeper: String,
+        min_value: Uint128,
+    },
+    /// Withdraws pending Archway rewards records and distributes them. Admin or keeper only.
+    HarvestArchwayRewards {
+        limit: Option<u64>,
+    },
+    /// Updates the Archway rewards owner and/or rewards address. Admin only.
+    UpdateArchwayMetadata {
+        owner_address: Option<String>,
+        rewards_address: Option<String>,
+    },
+    /// Sets the Archway flat fee charged per contract call. Admin only.
+    SetArchwayFlatFee {
+        amount: Coin,
+    },
+    /// Registers or replaces a chain in the registry. Admin only.
+    SetChain {
+        chain: ChainInfo,
+    },
+    /// Removes a chain no basket references. Admin only.
+    RemoveChain {
+        id: String,
+    },
+    /// Sends the messages of one withdrawal leg. Only callable by the contract
+    /// itself, so a multi-message leg succeeds or fails as a unit.
+    ExecuteOutflowLeg {
+        msgs: Vec<CosmosMsg<ArchwayMsg>>,
+    },
+    /// Applies a packet received on a basket IBC channel. Only callable by the
+    /// contract itself, so a failing packet reverts as a unit.
+    ProcessIbcPacket {
+        channel_id: String,
+        packet: BasketPacket,
+    },
+    /// Allows or disallows opening basket channels with a counterparty. Admin only.
+    SetIbcCounterparty {
+        counterparty: IbcCounterparty,
+        allowed: bool,
+    },
+    /// Invests the single coin of an ICS-20 transfer whose IBC-hooks memo made
+    /// this call, for `sender` on the other end of basket channel `channel_id`.
+    /// Only the hooks address derived for `sender` may call it.
+    IbcInvest {
+        channel_id: String,
+        sender: String,
+        basket_name: String,
+        #[serde(default)]
+        min_value: Uint128,
+        auto_compound: bool,
+    },
+    UpdateConfig(ConfigUpdate),
+}
+
+
+/// Config fields the admin can change; `None` leaves a field untouched.
+#[cw_serde]
+#[derive(Default)]
+pub struct ConfigUpdate {
+    pub reward_rate: Option<Uint128>,
+    pub min_lock_period: Option<u64>,
+    pub compound_frequency: Option<u64>,
+    pub keeper_tip_bps: Option<u16>,
+    pub keepers: Option<Vec<String>>,
+    pub treasury: Option<String>,
+    pub harvest_treasury_bps: Option<u16>,
+    pub dex_router: Option<String>,
+    pub max_slippage_bps: Option<u16>,
+    pub ibc_timeout_seconds: Option<u64>,
+    pub oracle: Option<String>,
+    pub price_max_age: Option<u64>,
+}
+
+/// Execute interface of the configured DEX router. The offer coin is sent as funds.
+#[cw_serde]
+pub enum RouterExecuteMsg {
+    Swap {
+        ask_denom: String,
+        minimum_receive: Uint128,
+    },
+}
+
+/// Packet sent by a paired contract on another chain on behalf of its users.
+#[cw_serde]
+pub struct BasketPacket {
+    pub version: u32,
+    pub msg: BasketPacketMsg,
+}
+
+/// Investments carry funds, so they arrive as ICS-20 transfers calling
+/// `ExecuteMsg::IbcInvest` rather than as basket packets.
+#[cw_serde]
+pub enum BasketPacketMsg {
+    /// Burns `shares` and sends the released tokens back to `sender` over ICS-20.
+    Withdraw {
+        sender: String,
+        basket_name: String,
+        shares: Uint128,
     },
 }
 
+/// Payout routing shared by every withdraw variant; see `ExecuteMsg::Withdraw`.
+#[cw_serde]
+#[derive(Default)]
+pub struct WithdrawOptions {
+    pub remote_recipient: Option<String>,
+    #[serde(default)]
+    pub destinations: Vec<ChainDestination>,
+    #[serde(default)]
+    pub zap_out: Option<ZapOut>,
+    #[serde(default)]
+    pub owner: Option<String>,
+}
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+/// Set as response data by every withdraw variant.
+#[cw_serde]
+pub struct WithdrawResponse {
+    pub shares: Uint128,
+    /// Market value of the redeemed shares at oracle prices.
+    pub value: Uint128,
+    /// Component tokens released from the basket. A zap-out swaps these
+    /// before paying out.
+    pub payouts: Vec<Coin>,
+}
+
+#[cw_serde]
+pub struct ZapOut {
+    /// Basket component or registered token to receive; it must be paid out
+    /// on this chain.
+    pub output_symbol: String,
+    /// Least amount of the output token the whole withdrawal must yield.
+    pub min_output: Uint128,
+}
+
+#[cw_serde]
+pub struct PriceUpdate {
+    pub symbol: String,
+    pub price: Uint128,
+}
+
+/// Remote end of a basket channel: the paired contract's port on the
+/// connection to its chain.
+#[cw_serde]
+pub struct IbcCounterparty {
+    pub port_id: String,
+    pub connection_id: String,
+}
+
+#[cw_serde]
+pub struct ChainDestination {
+    /// Chain registry id.
+    pub chain: String,
+    pub address: String,
+}
+
+/// Returned in a successful ack.
+#[cw_serde]
+pub struct BasketPacketResult {
+    /// Shares burned by this packet.
+    pub shares: Uint128,
+    /// Shares the remote user holds in the basket afterwards.
+    pub position_shares: Uint128,
+    pub value: Uint128,
+}
+
+#[cw_serde]
+pub enum BasketPacketAck {
+    Result(Binary),
+    Error(String),
+}
+
+/// Sudo callbacks delivered by the IBC hooks middleware for packets we sent.
+#[cw_serde]
+pub enum SudoMsg {
+    #[serde(rename = "ibc_lifecycle_complete")]
+    IBCLifecycleComplete(IBCLifecycleComplete),
+}
+
+#[cw_serde]
+pub enum IBCLifecycleComplete {
+    #[serde(rename = "ibc_ack")]
+    IBCAck {
+        channel: String,
+        sequence: u64,
+        ack: String,
+        success: bool,
+    },
+    #[serde(rename = "ibc_timeout")]
+    IBCTimeout { channel: String, sequence: u64 },
+}
+
+/// Memo asking the IBC hooks middleware to report a transfer's ack or timeout
+/// back to `ibc_callback` through `SudoMsg::IBCLifecycleComplete`.
+#[cw_serde]
+pub struct IbcCallbackMemo {
+    pub ibc_callback: String,
+}
+
+/// `ibc.applications.transfer.v1.MsgTransfer`, sent as a Stargate message
+/// because `IbcMsg::Transfer` cannot carry a memo.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct MsgTransfer {
+    #[prost(string, tag = "1")]
+    pub source_port: String,
+    #[prost(string, tag = "2")]
+    pub source_channel: String,
+    #[prost(message, optional, tag = "3")]
+    pub token: Option<ProtoCoin>,
+    #[prost(string, tag = "4")]
+    pub sender: String,
+    #[prost(string, tag = "5")]
+    pub receiver: String,
+    #[prost(uint64, tag = "7")]
+    pub timeout_timestamp: u64,
+    #[prost(string, tag = "8")]
+    pub memo: String,
+}
+
+/// `cosmos.base.v1beta1.Coin`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ProtoCoin {
+    #[prost(string, tag = "1")]
+    pub denom: String,
+    #[prost(string, tag = "2")]
+    pub amount: String,
+}
+
+/// Execute messages understood by the supported bridge schemas.
+#[cw_serde]
+pub enum BridgeExecuteMsg {
+    Bridge {
+        destination_chain: String,
+        recipient: String,
+    },
+    TransferRemote {
+        dest_domain: u32,
+        recipient: HexBinary,
+        amount: Uint128,
+    },
+    DepositTokens {},
+    InitiateTransfer {
+        asset: WormholeAsset,
+        recipient_chain: u16,
+        recipient: Binary,
+        fee: Uint128,
+        nonce: u32,
+    },
+}
+
+#[cw_serde]
+pub struct WormholeAsset {
+    pub info: WormholeAssetInfo,
+    pub amount: Uint128,
+}
+
+#[cw_serde]
+pub enum WormholeAssetInfo {
+    NativeToken { denom: String },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, QueryResponses)]
 pub enum QueryMsg {
+    #[returns(Config)]
     GetConfig {},
+    #[returns(BasketConfig)]
     GetBasket {
         name: String,
     },
+    #[returns(UserPortfolio)]
     GetPortfolio {
         address: String,
     },
+    #[returns(Vec<InvestmentHistory>)]
     GetInvestmentHistory {
         address: String,
         from_timestamp: Option<u64>,
         to_timestamp: Option<u64>,
     },
+    #[returns(Vec<(String, i64)>)]
     GetPerformanceMetrics {
         address: String,
         basket_name: Option<String>,
     },
+    #[returns(Uint128)]
     GetRewards {
         address: String,
     },
+    /// Reward tokens funded and not yet paid out.
+    #[returns(Uint128)]
+    GetRewardPool {},
+    #[returns(ArchwayConfigResponse)]
+    GetArchwayConfig {},
+    #[returns(BasketDriftResponse)]
+    GetBasketDrift {
+        name: String,
+    },
+    #[returns(ChainInfo)]
+    GetChain {
+        id: String,
+    },
+    #[returns(Vec<ChainInfo>)]
+    ListChains {},
+    #[returns(Vec<BasketVersion>)]
+    /// Every recorded composition of a basket, oldest first.
+    ListBasketVersions {
+        basket_name: String,
+    },
+    #[returns(BasketVersion)]
+    /// A basket's composition at a given version or block time (seconds).
+    GetBasketVersion {
+        basket_name: String,
+        version: Option<u32>,
+        at_time: Option<u64>,
+    },
+    #[returns(Vec<Token>)]
+    ListRegisteredTokens {},
+    #[returns(Option<Curation>)]
+    GetCuration {
+        basket_name: String,
+    },
+    #[returns(Option<ExitTriggers>)]
+    GetExitTriggers {
+        owner: String,
+        basket_name: String,
+    },
+    #[returns(DcaSchedule)]
+    GetDcaSchedule {
+        id: u64,
+    },
+    #[returns(Vec<DcaSchedule>)]
+    ListDcaSchedules {
+        owner: String,
+    },
+    #[returns(Vec<OperatorApproval>)]
+    /// Unexpired operator approvals granted by `owner`.
+    ListOperators {
+        owner: String,
+    },
+    #[returns(Vec<PendingWithdrawal>)]
+    /// Withdrawals with outflows still in flight, optionally for one address.
+    ListPendingWithdrawals {
+        address: Option<String>,
+    },
+    #[returns(Vec<IbcCounterparty>)]
+    /// Counterparties basket channels may be opened with.
+    ListIbcCounterparties {},
 }
 
 // We define a custom struct for each query response
 #[cw_serde]
-pub struct GetCountResponse {
-    pub count: i32,
+pub struct ComponentDrift {
+    pub symbol: String,
+    pub value: Uint128,
+    pub current_weight_bps: u32,
+    pub target_weight_bps: u32,
+    pub drift_bps: i32,
+}
+
+#[cw_serde]
+pub struct BasketDriftResponse {
+    pub total_value: Uint128,
+    pub needs_rebalance: bool,
+    pub components: Vec<ComponentDrift>,
+}
+
+#[cw_serde]
+pub struct ArchwayConfigResponse {
+    pub owner_address: Option<String>,
+    pub rewards_address: Option<String>,
+    pub flat_fee: Option<Coin>,
 }
 
 
@@ -3,17 +3,9 @@ use std::collections::HashMap;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::{Addr, Uint128};
+use cosmwasm_std::Uint128;
 use cw_storage_plus::{Item, Map};
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
-pub struct State {
-    pub count: i32,
-    pub owner: Addr,
-}
-
-pub const STATE: Item<State> = Item::new("state");
-
 pub const PORTFOLIOS: Map<&str, UserPortfolio> = Map::new("portfolios");
 pub const REWARD_CONFIG: Item<RewardConfig> = Item::new("reward_config");
 pub const PRICE_FEEDS: Map<&str, Uint128> = Map::new("price_feeds");